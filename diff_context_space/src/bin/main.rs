@@ -1,287 +1,1035 @@
 use std::fs::File;
-use std::path::PathBuf;
-use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::fmt;
 
 extern crate diff_context_space;
-use diff_context_space::{Transformation, Information, ContextSpace, Context};
+use diff_context_space::{Transformation, Information, ContextSpace, MappedContextSpace, Context};
+use diff_context_space::{ConsolidationPolicy, ConsolidationSchedule, RuleMode, Checkpoint};
+use diff_context_space::{LearningMode, SAME_TRANSFORMATION_DISTANCE, Interpolation, Resampling, DistanceMetric};
+use diff_context_space::{TransformationSetBuilder, Sequence, SequenceTransformations, EvalReport};
+use diff_context_space::{Dataset, Holdout, sample_order, image_paths};
 
 extern crate num_traits;
 use num_traits::int::PrimInt;
 
-extern crate bincode;
-
 extern crate serde;
 use serde::{Serialize};
+use serde::de::DeserializeOwned;
+
+extern crate serde_json;
 
 extern crate image;
+use image::GenericImageView;
 use image::imageops::{resize, FilterType};
 
-// to pass arguments with cargo: cargo run -- load -t cs -f 'some file to load'
+extern crate clap;
+use clap::{Parser, Subcommand, ValueEnum};
 
-fn main() {
-    // generate_and_save_64x64_xya_transformations();
-    // transform_32x32_to_64x64();
-    // teach_context_space_by_64x64_cc_mono_icon_set_and_save();
-    load_context_space_and_learn_new_information();
-    // transform_and_save_image();
-    // print_transformations();
+type CliResult = Result<(), Box<dyn std::error::Error>>;
+
+/// Command line interface to build, teach and check context spaces.
+/// Example: cargo run -- train -i files/interpretations/64x64/cc_mono_icon_set
+/// -t files/transformations/t_64x64.bin -o files/out/context_spaces/cs_64x64.bin
+#[derive(Parser)]
+#[command(name = "main", about = "Differential context space experiments")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generates the set of transformations and saves it into a file.
+    GenTransformations {
+        /// Output file of the transformations set.
+        #[arg(short, long)]
+        out: PathBuf,
+        /// Image size the transformations are built for,
+        /// the shifts go up to a quarter of it both ways if no range is given.
+        #[arg(short, long, default_value_t = 64)]
+        size: u8,
+        /// Horizontal shifts as MIN:MAX or N for -N:N.
+        #[arg(long, value_parser = parse_range, allow_hyphen_values = true)]
+        x_range: Option<(i16, i16)>,
+        /// Vertical shifts as MIN:MAX or N for -N:N.
+        #[arg(long, value_parser = parse_range, allow_hyphen_values = true)]
+        y_range: Option<(i16, i16)>,
+        /// Step between the shifts.
+        #[arg(long, default_value_t = 1)]
+        shift_step: i16,
+        /// Adds rotated transformations for every shift, by π/4 if no angles are given.
+        #[arg(short, long)]
+        rotation: bool,
+        /// Angles from 0 up to π with this step in radians.
+        #[arg(long)]
+        angle_step: Option<f32>,
+        /// Explicit angles in radians, comma separated.
+        #[arg(long, value_delimiter = ',', allow_hyphen_values = true)]
+        angles: Vec<f32>,
+        /// Scales other than 1 to add for every shift, comma separated.
+        #[arg(long, value_delimiter = ',')]
+        scales: Vec<f32>,
+        /// Adds horizontally and vertically flipped transformations for every shift.
+        #[arg(long)]
+        flips: bool,
+    },
+    /// Converts a transformations set between the bincode, JSON and CSV files,
+    /// the formats are given by the extensions .json and .csv, other files are bincode.
+    ConvertTransformations {
+        /// File of the transformations set.
+        #[arg(short, long)]
+        input: PathBuf,
+        /// Output file of the transformations set.
+        #[arg(short, long)]
+        out: PathBuf,
+    },
+    /// Resizes every image of the input folder and saves it into the output folder.
+    ResizeDataset {
+        /// Folder with the source images.
+        #[arg(short, long)]
+        input: PathBuf,
+        /// Folder to save the resized images into.
+        #[arg(short, long)]
+        out: PathBuf,
+        /// Width and height of the resized images.
+        #[arg(short, long)]
+        size: u32,
+    },
+    /// Teaches a new context space by every image of the folder
+    /// moved by every transformation of the set and saves it.
+    Train {
+        /// Folder with the images to learn.
+        #[arg(short, long)]
+        images: PathBuf,
+        /// File of the transformations set.
+        #[arg(short, long)]
+        transformations: PathBuf,
+        /// Output file of the context space.
+        #[arg(short, long)]
+        out: PathBuf,
+        #[command(flatten)]
+        model: ModelArgs,
+        /// Skips the transformations with rotation.
+        #[arg(long)]
+        no_rotation: bool,
+        #[command(flatten)]
+        consolidation: ConsolidationArgs,
+        /// Rules keep the bits set in at least this part of the samples
+        /// instead of the bits set in every sample.
+        #[arg(long)]
+        counting_threshold: Option<f32>,
+        #[command(flatten)]
+        checkpoint: CheckpointArgs,
+        #[command(flatten)]
+        distance: DistanceArgs,
+        #[command(flatten)]
+        resampling: ResamplingArgs,
+        /// Learns the samples in the order shuffled by the seed instead of
+        /// image by image. The same seed gives the same context space.
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+    /// Teaches a context space every pair of the consecutive frames
+    /// of an animated gif or of a folder of images and saves it.
+    LearnSequence {
+        /// Animated gif or folder with the frames in the order of their names.
+        #[arg(short, long)]
+        frames: PathBuf,
+        /// Output file of the context space.
+        #[arg(short, long)]
+        out: PathBuf,
+        /// Context space to continue teaching, a new one is started if omitted.
+        #[arg(short, long)]
+        cs: Option<PathBuf>,
+        #[command(flatten)]
+        model: ModelArgs,
+        /// File with the transformation for every pair of the frames.
+        /// If omitted, the sidecar file next to the frames is used if there is one.
+        #[arg(short, long)]
+        transformations: Option<PathBuf>,
+        /// Transformations set to infer the transformations among
+        /// when there is no sidecar file.
+        #[arg(long)]
+        candidates: Option<PathBuf>,
+        /// Pairs of the frames less similar than this under the inferred
+        /// transformation are not learnt.
+        #[arg(long, default_value_t = 0.9)]
+        min_similarity: f32,
+        /// Minimal accuracy of the interpretations in the trace.
+        #[arg(short, long, default_value_t = 0.9)]
+        accuracy: f32,
+        /// Output JSON file of the trace of every frame.
+        #[arg(long)]
+        trace: Option<PathBuf>,
+    },
+    /// Interprets an image by the saved context space.
+    Interpret {
+        /// File of the context space.
+        #[arg(short, long)]
+        cs: PathBuf,
+        /// Image to interpret.
+        #[arg(short, long)]
+        image: PathBuf,
+        #[command(flatten)]
+        model: ModelArgs,
+        /// Minimal accuracy of the interpretation.
+        #[arg(short, long, default_value_t = 0.9)]
+        accuracy: f32,
+        #[command(flatten)]
+        tran: TransformationArgs,
+        #[command(flatten)]
+        resampling: ResamplingArgs,
+        /// The context space file was written by save-mapped.
+        #[arg(long)]
+        mapped: bool,
+        /// Prints up to K best candidates instead of the best one.
+        #[arg(long, conflicts_with = "mapped")]
+        top_k: Option<usize>,
+        /// Prints the result as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Moves every image of the folder by every transformation of the set,
+    /// interprets the result and reports how it matches the expected one.
+    Eval {
+        /// File of the context space.
+        #[arg(short, long)]
+        cs: PathBuf,
+        /// Folder with the images to evaluate.
+        #[arg(short, long)]
+        images: PathBuf,
+        /// File of the transformations set.
+        #[arg(short, long)]
+        transformations: PathBuf,
+        /// Output JSON file of the metrics and the confusion matrix.
+        #[arg(long)]
+        json: Option<PathBuf>,
+        /// Output CSV file of the metrics.
+        #[arg(long)]
+        csv: Option<PathBuf>,
+        /// Output CSV file of the confusion matrix.
+        #[arg(long)]
+        confusion: Option<PathBuf>,
+        #[command(flatten)]
+        model: ModelArgs,
+        /// Minimal accuracy of the interpretation.
+        #[arg(short, long, default_value_t = 0.9)]
+        accuracy: f32,
+        /// Teaches the context space every image without transformation before evaluation.
+        #[arg(long)]
+        learn: bool,
+        /// Skips the transformations with rotation.
+        #[arg(long)]
+        no_rotation: bool,
+        #[command(flatten)]
+        resampling: ResamplingArgs,
+    },
+    /// Splits the images and the transformations into k folds by the seed,
+    /// teaches a new context space every fold but one and evaluates it
+    /// by the remaining fold.
+    CrossValidate {
+        /// Folders with the images.
+        #[arg(short, long, num_args = 1.., required = true)]
+        images: Vec<PathBuf>,
+        /// File of the transformations set.
+        #[arg(short, long)]
+        transformations: PathBuf,
+        /// Number of the folds.
+        #[arg(short, long, default_value_t = 5)]
+        k: usize,
+        /// Seed of the split, the same seed gives the same folds.
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// What is split into the folds.
+        #[arg(long, value_enum, default_value_t = HoldoutArg::Images)]
+        holdout: HoldoutArg,
+        /// Output JSON file of the metrics of every fold.
+        #[arg(long)]
+        json: Option<PathBuf>,
+        #[command(flatten)]
+        model: ModelArgs,
+        /// Minimal accuracy of the interpretation.
+        #[arg(short, long, default_value_t = 0.9)]
+        accuracy: f32,
+        /// Skips the transformations with rotation.
+        #[arg(long)]
+        no_rotation: bool,
+        #[command(flatten)]
+        resampling: ResamplingArgs,
+    },
+    /// Saves the rules of every active context into a gif file.
+    DumpRules {
+        /// File of the context space.
+        #[arg(short, long)]
+        cs: PathBuf,
+        /// Output gif file.
+        #[arg(short, long)]
+        out: PathBuf,
+        #[command(flatten)]
+        model: ModelArgs,
+    },
+    /// Prints how much memory a context space takes.
+    Memory {
+        /// File of the context space.
+        #[arg(short, long)]
+        cs: PathBuf,
+        #[command(flatten)]
+        model: ModelArgs,
+    },
+    /// Saves the context space in the read-only format which is
+    /// memory mapped instead of being loaded.
+    SaveMapped {
+        /// File of the context space.
+        #[arg(short, long)]
+        cs: PathBuf,
+        /// Output file of the mapped context space.
+        #[arg(short, long)]
+        out: PathBuf,
+        #[command(flatten)]
+        model: ModelArgs,
+    },
+    /// Prints the header of a context space file without loading it.
+    Info {
+        /// File of the context space.
+        #[arg(short, long)]
+        cs: PathBuf,
+    },
+    /// Converts a context space saved before the file header was introduced
+    /// into the current format.
+    Migrate {
+        /// File of the old context space.
+        #[arg(short, long)]
+        input: PathBuf,
+        /// Output file of the context space.
+        #[arg(short, long)]
+        out: PathBuf,
+        #[command(flatten)]
+        model: ModelArgs,
+    },
+    /// Applies a transformation to an image and saves the result.
+    RenderTransformation {
+        /// Image to transform.
+        #[arg(short, long)]
+        image: PathBuf,
+        /// Output image file.
+        #[arg(short, long)]
+        out: PathBuf,
+        #[command(flatten)]
+        model: ModelArgs,
+        #[command(flatten)]
+        tran: TransformationArgs,
+        #[command(flatten)]
+        resampling: ResamplingArgs,
+    },
+}
+
+/// Settings of the information the context space works with.
+#[derive(clap::Args)]
+struct ModelArgs {
+    /// Bit width of the integer keeping a piece of an image row: 8, 16, 32 or 64.
+    #[arg(short = 'w', long, default_value_t = 64)]
+    int_width: u32,
+    /// Size of the images as WxH or N for square ones.
+    /// Images of another size are rejected. Any size is accepted if omitted.
+    #[arg(short, long, value_parser = parse_size)]
+    size: Option<(u32, u32)>,
+}
+
+fn parse_range(s: &str) -> Result<(i16, i16), String> {
+    let parse = |v: &str| v.trim().parse::<i16>().map_err(|e| format!("{}: {}", v, e));
+    match s.split_once(':') {
+        Some((min, max)) => Ok((parse(min)?, parse(max)?)),
+        None => {
+            let n = parse(s)?.abs();
+            Ok((-n, n))
+        }
+    }
+}
+
+fn parse_size(s: &str) -> Result<(u32, u32), String> {
+    let parse = |v: &str| v.trim().parse::<u32>().map_err(|e| format!("{}: {}", v, e));
+    match s.split_once('x') {
+        Some((w, h)) => Ok((parse(w)?, parse(h)?)),
+        None => {
+            let n = parse(s)?;
+            Ok((n, n))
+        }
+    }
+}
+
+/// Consolidation of the contexts during the training.
+#[derive(clap::Args)]
+struct ConsolidationArgs {
+    /// Consolidates the contexts after every N learnt samples.
+    #[arg(long)]
+    consolidate_every: Option<usize>,
+    /// Rules learnt from fewer samples are not consolidated.
+    #[arg(long, default_value_t = 3.0)]
+    min_support: f32,
+    /// Part of the samples where a bit had to be set to stay in a rule.
+    #[arg(long, default_value_t = 0.75)]
+    min_hit_ratio: f32,
+    /// Number of the latest samples the rule statistics represents.
+    #[arg(long)]
+    decay_window: Option<f32>,
 }
 
-fn generate_and_save_64x64_xya_transformations() {
-    let env_path = env!("CARGO_MANIFEST_DIR");
-    let mut out_file_path = PathBuf::from(env_path);
-    out_file_path.push("files/transformations/t_64x64.bin");
-    // 32 because transformations for 64x64 are half of possible movements
-    diff_context_space::build_and_save_transformations(out_file_path, 32, false);
+/// Grouping of the contexts by the distance between their transformations.
+#[derive(clap::Args)]
+struct DistanceArgs {
+    /// Contexts within this distance of the sample transformation learn the sample.
+    #[arg(long, default_value_t = SAME_TRANSFORMATION_DISTANCE)]
+    learning_radius: f32,
+    /// Contexts learn the sample with the weight falling with the distance.
+    #[arg(long)]
+    neighbourhood: bool,
+    /// Pixels of the shift one radian of the rotation is worth,
+    /// a half of the image size by default.
+    #[arg(long)]
+    angle_weight: Option<f32>,
+    /// Measures the shifts around the edges of the image.
+    #[arg(long)]
+    wrap: bool,
 }
 
-/// Transforms existing images from 32x32 folder into 
-/// 64x64 saving them into the appropriate folder.
-fn transform_32x32_to_64x64() {
-    let env_path = env!("CARGO_MANIFEST_DIR");
-    
-    let mut i_path = PathBuf::from(env_path);
-    i_path.push("files/interpretations/32x32/cc_mono_icon_set/");
-    let dir_entries = std::fs::read_dir(i_path).unwrap();
+/// Interpolation of the rotated images.
+#[derive(clap::Args)]
+struct ResamplingArgs {
+    /// Interpolation of the pixels of the rotated image.
+    #[arg(long, value_enum, default_value_t = InterpolationArg::Nearest)]
+    interpolation: InterpolationArg,
+    /// Pixels of the interpolated image brighter than this are set.
+    #[arg(long, default_value_t = Resampling::default().threshold)]
+    binarize_threshold: u8,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum InterpolationArg {
+    Nearest,
+    Bilinear,
+    Bicubic,
+}
+
+impl ResamplingArgs {
+    fn to_resampling(&self) -> Resampling {
+        let interpolation = match self.interpolation {
+            InterpolationArg::Nearest => Interpolation::Nearest,
+            InterpolationArg::Bilinear => Interpolation::Bilinear,
+            InterpolationArg::Bicubic => Interpolation::Bicubic,
+        };
+        Resampling { interpolation, threshold: self.binarize_threshold }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum HoldoutArg {
+    Images,
+    Transformations,
+    Both,
+}
+
+/// Checkpoints of the training.
+#[derive(clap::Args)]
+struct CheckpointArgs {
+    /// Folder to save the training progress to and to resume it from.
+    #[arg(long)]
+    checkpoint_dir: Option<PathBuf>,
+    /// Saves the progress after every N learnt samples.
+    #[arg(long, default_value_t = 1000)]
+    checkpoint_every: usize,
+}
 
-    let mut o_path = PathBuf::from(env_path);
-    o_path.push("files/interpretations/64x64/cc_mono_icon_set/");
+impl ConsolidationArgs {
+    fn to_schedule(&self) -> Option<ConsolidationSchedule> {
+        let every = self.consolidate_every?;
+        let policy = ConsolidationPolicy {
+            min_support: self.min_support,
+            min_hit_ratio: self.min_hit_ratio,
+            decay_window: self.decay_window,
+        };
+        Some(ConsolidationSchedule { every, policy })
+    }
+}
 
-    const WIDTH:u32 = 64;
-    const HEIGHT:u32 = WIDTH;
+/// Transformation given by its components.
+#[derive(clap::Args)]
+struct TransformationArgs {
+    /// Horizontal shift, right is positive.
+    #[arg(short, long, default_value_t = 0, allow_hyphen_values = true)]
+    x: i16,
+    /// Vertical shift, up is positive.
+    #[arg(short, long, default_value_t = 0, allow_hyphen_values = true)]
+    y: i16,
+    /// Counter clockwise rotation angle in radians.
+    #[arg(long, default_value_t = 0.0, allow_hyphen_values = true)]
+    a: f32,
+    /// Zoom about the center, more than 1 zooms in.
+    #[arg(long, default_value_t = 1.0)]
+    scale: f32,
+    /// Mirrors the information left to right.
+    #[arg(long)]
+    flip_h: bool,
+    /// Mirrors the information top to bottom.
+    #[arg(long)]
+    flip_v: bool,
+}
+
+impl TransformationArgs {
+    fn to_transformation(&self) -> Transformation {
+        Transformation {
+            scale: self.scale,
+            flip_h: self.flip_h,
+            flip_v: self.flip_v,
+            ..Transformation::new(self.x, self.y, self.a)
+        }
+    }
+}
+
+/// Runs the given generic function with the integer type
+/// matching the requested width.
+macro_rules! with_int_width {
+    ($model:expr, $f:ident ( $($arg:expr),* )) => {
+        match $model.int_width {
+            8 => $f::<u8>($($arg),*),
+            16 => $f::<u16>($($arg),*),
+            32 => $f::<u32>($($arg),*),
+            64 => $f::<u64>($($arg),*),
+            w => Err(Box::new(CliError(format!("Unsupported integer width: {}.", w))).into()),
+        }
+    };
+}
+
+#[derive(Debug)]
+struct CliError(String);
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for CliError {}
+
+fn main() {
+    let cli = Cli::parse();
+
+    if let Err(e) = run(&cli) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: &Cli) -> CliResult {
+    match &cli.command {
+        Command::GenTransformations { out, size, x_range, y_range, shift_step, rotation, angle_step, angles, scales, flips } => {
+            let max_shift = (*size / 4) as i16;
+            let (x_min, x_max) = x_range.unwrap_or((-max_shift, max_shift));
+            let (y_min, y_max) = y_range.unwrap_or((-max_shift, max_shift));
+
+            let mut builder = TransformationSetBuilder::new()
+                .x_range(x_min, x_max)
+                .y_range(y_min, y_max)
+                .shift_step(*shift_step)
+                .scales(scales)
+                .flips(*flips);
+            if !angles.is_empty() {
+                builder = builder.angles(angles);
+            } else if let Some(step) = angle_step {
+                builder = builder.angle_step(*step);
+            } else if *rotation {
+                builder = builder.angle_step(std::f32::consts::FRAC_PI_4);
+            }
+
+            let count = builder.save(out)?;
+            println!("Generated {} transformations.", count);
+            Ok(())
+        },
+        Command::ConvertTransformations { input, out } => {
+            let ts = diff_context_space::load_transformations(input)?;
+            diff_context_space::save_transformations(out, &ts)?;
+            println!("Converted {} transformations.", ts.len());
+            Ok(())
+        },
+        Command::ResizeDataset { input, out, size } => {
+            resize_dataset(input, out, *size)
+        },
+        Command::Train { images, transformations, out, model, no_rotation, consolidation, counting_threshold, checkpoint, distance, resampling, seed } => {
+            let mode = match counting_threshold {
+                Some(threshold) => RuleMode::Counting { threshold: *threshold },
+                None => RuleMode::Intersection,
+            };
+            with_int_width!(model, train(images, transformations, out, model, *no_rotation, consolidation, mode, checkpoint, distance, resampling, *seed))
+        },
+        Command::LearnSequence { frames, out, cs, model, transformations, candidates, min_similarity, accuracy, trace } => {
+            let sources = SequenceSources { transformations, candidates, min_similarity: *min_similarity };
+            with_int_width!(model, learn_sequence(frames, out, cs, model, &sources, *accuracy, trace))
+        },
+        Command::Interpret { cs, image, model, accuracy, tran, resampling, mapped, top_k, json } => {
+            if let Some(k) = top_k {
+                return with_int_width!(model, interpret_top_k(cs, image, model, *accuracy, tran, resampling, *k, *json));
+            }
+            with_int_width!(model, interpret(cs, image, model, *accuracy, tran, resampling, *mapped, *json))
+        },
+        Command::Eval { cs, images, transformations, json, csv, confusion, model, accuracy, learn, no_rotation, resampling } => {
+            let outputs = EvalOutputs { json, csv, confusion };
+            with_int_width!(model, eval(cs, images, transformations, &outputs, model, *accuracy, *learn, *no_rotation, resampling))
+        },
+        Command::CrossValidate { images, transformations, k, seed, holdout, json, model, accuracy, no_rotation, resampling } => {
+            let holdout = match holdout {
+                HoldoutArg::Images => Holdout::Images,
+                HoldoutArg::Transformations => Holdout::Transformations,
+                HoldoutArg::Both => Holdout::Both,
+            };
+            with_int_width!(model, cross_validate(images, transformations, *k, *seed, holdout, json, model, *accuracy, *no_rotation, resampling))
+        },
+        Command::DumpRules { cs, out, model } => {
+            with_int_width!(model, dump_rules(cs, out))
+        },
+        Command::Memory { cs, model } => {
+            with_int_width!(model, memory(cs))
+        },
+        Command::SaveMapped { cs, out, model } => {
+            with_int_width!(model, save_mapped(cs, out))
+        },
+        Command::Info { cs } => {
+            info(cs)
+        },
+        Command::Migrate { input, out, model } => {
+            with_int_width!(model, migrate(input, out))
+        },
+        Command::RenderTransformation { image, out, model, tran, resampling } => {
+            with_int_width!(model, render_transformation(image, out, model, tran, resampling))
+        },
+    }
+}
+
+/// Resizes existing images from the input folder
+/// saving them into the output one.
+fn resize_dataset(input: &Path, out: &Path, size: u32) -> CliResult {
+    let dir_entries = std::fs::read_dir(input)?;
+    std::fs::create_dir_all(out)?;
 
     for dr in dir_entries {
-        let dir_entry = dr.unwrap();
+        let dir_entry = dr?;
         let file_path = dir_entry.path();
-        
+
         match image::open(&file_path) {
             Ok(img) => {
-                let i = resize(&img, WIDTH, HEIGHT, FilterType::Nearest);
+                let i = resize(&img, size, size, FilterType::Nearest);
 
-                let mut full_out_path = o_path.clone();
+                let mut full_out_path = out.to_path_buf();
                 full_out_path.push(dir_entry.file_name());
-                i.save_with_format(full_out_path, image::ImageFormat::Png).expect("Unable to save image file");
+                i.save_with_format(full_out_path, image::ImageFormat::Png)?;
 
-                println!("Processed: {}", &file_path.into_os_string().into_string().unwrap());
+                println!("Processed: {}", file_path.display());
             },
             Err(e) => {
-                println!("{}: {}", e, &file_path.into_os_string().into_string().unwrap());
+                println!("{}: {}", e, file_path.display());
             }
         }
     }
-}
 
-fn load_context_space_and_learn_new_information() {
-    let env_path = env!("CARGO_MANIFEST_DIR");
-    let mut cs_path = PathBuf::from(env_path);
-    cs_path.push("files/out/context_spaces/cs_64x64_mono_icon_set.bin");
+    Ok(())
+}
 
+#[allow(clippy::too_many_arguments)]
+fn train<T>(images: &Path, transformations: &Path, out: &Path, model: &ModelArgs,
+    no_rotation: bool, consolidation: &ConsolidationArgs, mode: RuleMode, checkpoint: &CheckpointArgs, distance: &DistanceArgs,
+    resampling: &ResamplingArgs, seed: Option<u64>) -> CliResult
+    where T: PrimInt + Serialize + DeserializeOwned + Sync + Send {
     let now = std::time::SystemTime::now();
 
-    let mut cs = ContextSpace::<u64>::load(cs_path).unwrap();
+    let ts = load_transformations(transformations, no_rotation)?;
+    let resampling = resampling.to_resampling();
 
-    println!("Loading context space 64x64 took {} seconds.", now.elapsed().unwrap().as_secs());
+    let mut ints = Vec::new();
+    for img_path in image_paths(images)? {
+        ints.push(load_information::<T>(&img_path, model)?);
+    }
 
-    // load transformations
+    let mut cs = ContextSpace::<T>::new();
+    cs.consolidation = consolidation.to_schedule();
+    cs.rule_mode = mode;
+    // the rotations, scales and flips are weighted by the shifts they make at the border
+    if let Some(int) = ints.first() {
+        cs.distance = DistanceMetric::for_size(int.width, int.height);
+    }
+    if let Some(angle_weight) = distance.angle_weight {
+        cs.distance.angle_weight = angle_weight;
+    }
+    cs.learning_radius = distance.learning_radius;
+    if distance.neighbourhood {
+        cs.learning_mode = LearningMode::Neighbourhood;
+    }
+    if distance.wrap {
+        if let Some(int) = ints.first() {
+            cs.distance.wrap = Some((int.width, int.height));
+        }
+    }
+
+    // the checkpoint refuses to be resumed with the settings other than it was started with
+    let mut cp = match &checkpoint.checkpoint_dir {
+        None => None,
+        Some(dir) => {
+            let (cp, restored) = Checkpoint::resume(dir, cs)?;
+            cs = restored;
+            if cp.done_count() > 0 {
+                println!("Resumed training with {} learnt samples and the same settings.", cp.done_count());
+            } else {
+                println!("Started training with a new checkpoint.");
+            }
+            Some(cp)
+        }
+    };
 
+    println!("Loaded transformations and images to learn took {} seconds.", now.elapsed()?.as_secs());
     let now = std::time::SystemTime::now();
 
-    let mut ts_path = PathBuf::from(env_path);
-    ts_path.push("files/transformations/t_64x64.bin");
-    let ts = load_transformations(&ts_path);
+    for (int_idx, t_idx) in sample_order(ints.len(), ts.len(), seed) {
+        let int = &ints[int_idx];
+        let t = &ts[t_idx];
+        if cp.as_ref().is_some_and(|cp| cp.is_done(&int.name, t)) {
+            continue;
+        }
 
-    println!("N of transformations: {}", ts.len());
+        let i = t.apply_with(int, &resampling);
+        cs.learn(t, &i, int.clone())?;
 
-    println!("Loading transformations t_64x64 took {} seconds.", now.elapsed().unwrap().as_secs());
-
-    // create log file
-    let mut log_path = PathBuf::from(env_path);
-    log_path.push("files/out/logs/int_log_64x64.log");
-    let mut log_file = File::create(log_path).unwrap();
-    
-    for idx in 1..2 {
-        // take image from galaxy
-        let mut galaxy_img_path = PathBuf::from(env_path);
-        galaxy_img_path.push(format!("files/interpretations/64x64/galaxy/{}.png", idx));
-
-        let img = image::open(galaxy_img_path).unwrap();
-        let expected_name = format!("{}.png", idx);
-        let int = Information::<u64>::from_image(&img, expected_name.clone());
-
-        // teach context space with new image, no transformation
-        let t = Transformation { y: 0, x: 0, a: 0.0 };
-        cs.learn(&t, &int, int.clone());
-
-        for t in &ts {
-            // transform image
-            let i = t.apply_to(&int);
-
-            match cs.interpret(&i, 0.9) {
-                None => {
-                    writeln!(&mut log_file, "Interpretation could not been found, transformation: {}.", t).unwrap()
-                },
-                Some(data) => {
-                    // //save existing interpretation image
-                    // let mut existing_int_out_path = PathBuf::from(env_path);
-                    // existing_int_out_path.push(format!("files/out/interpretations/{}_{}_existing.png", idx, t));
-                    // let existing_int_img = data.0.to_image();
-                    // existing_int_img.save_with_format(existing_int_out_path, image::ImageFormat::Png).expect("Unable to save image file");
-
-                    // //save actual image
-                    // let mut actual_int_out_path = PathBuf::from(env_path);
-                    // actual_int_out_path.push(format!("files/out/interpretations/{}_{}_actual.png", idx, t));
-                    // let actual_int_img = data.3.to_image();
-                    // actual_int_img.save_with_format(actual_int_out_path, image::ImageFormat::Png).expect("Unable to save image file");
-
-                    //print interpretation info
-                    let t_selected = data.1;
-                    let int_existing = data.0;
-                    let int_actual = data.3;
-                    let accuracy = data.2;
-                    let coherence = int_existing.coherence_to(&int_actual).unwrap();
-                    let t_match = t_selected == *t;
-                    let int_match = int_existing.name == expected_name;
-
-                    let tdx = (t_selected.x - t.x).abs();
-                    let tdy = (t_selected.y - t.y).abs();
-                    let tda = (t_selected.a - t.a).abs();
-
-                    writeln!(&mut log_file, "int: {}, sel int: {}, int_match: {}, t_match: {}, acc: {}, coh: {}, t: {}, sel_t: {}, t_dx: {}, t_dy: {}, t_da: {}.",
-                        expected_name.clone(),
-                        int_existing.name,
-                        int_match,
-                        t_match,
-                        accuracy,
-                        coherence,
-                        t,
-                        t_selected,
-                        tdx,
-                        tdy,
-                        tda
-                        ).unwrap();
-                }
+        if let Some(cp) = cp.as_mut() {
+            cp.mark_done(int.name.clone(), t.clone());
+            if checkpoint.checkpoint_every > 0 && cs.samples_count.is_multiple_of(checkpoint.checkpoint_every) {
+                cp.save(&mut cs)?;
             }
         }
     }
-}
 
-fn teach_context_space_by_64x64_cc_mono_icon_set_and_save() {
-    let now = std::time::SystemTime::now();
-    
-    let env_path = env!("CARGO_MANIFEST_DIR");
+    // consolidate the samples learnt after the last scheduled consolidation
+    if let Some(schedule) = cs.consolidation.clone() {
+        cs.consolidate(&schedule.policy);
+    }
 
-    let mut ts_path = PathBuf::from(env_path);
-    ts_path.push("files/transformations/t_64x64.bin");
-    let ts = load_transformations(&ts_path);
+    if let Some(cp) = cp.as_mut() {
+        cp.save(&mut cs)?;
+    }
 
-    let mut cs = ContextSpace::<u64>::new();
+    println!("Learning took {} seconds.", now.elapsed()?.as_secs());
 
-    let mut img_folder_path = PathBuf::from(env_path);
-    img_folder_path.push("files/interpretations/64x64/cc_mono_icon_set/");
-    let dir_entries = std::fs::read_dir(img_folder_path).unwrap();
+    let all_count = cs.contexts.len();
+    let active_count = cs.contexts.iter().filter(|c| !c.rules.is_empty()).count();
+    println!("All contexts count: {}, active: {}, transformations count: {}.", all_count, active_count, &ts.len());
+    println!("{}", cs.memory_usage());
 
-    println!("Loaded transformations and reading images to learn took {} seconds.", now.elapsed().unwrap().as_secs());
     let now = std::time::SystemTime::now();
 
-    for dr in dir_entries {
-        let dir_entry = dr.unwrap();
-        let img_path = dir_entry.path();
+    cs.save(out)?;
+
+    println!("Saving of the context space file took {} seconds.", now.elapsed()?.as_secs());
+    Ok(())
+}
+
+/// Where learn-sequence takes the transformations from.
+struct SequenceSources<'a> {
+    transformations: &'a Option<PathBuf>,
+    candidates: &'a Option<PathBuf>,
+    min_similarity: f32,
+}
 
-        let name = String::from(img_path.file_name().unwrap().to_str().unwrap());
-        let img = image::open(img_path).unwrap();
-        let int = Information::<u64>::from_image(&img, name);
+fn learn_sequence<T>(frames: &Path, out: &Path, cs_path: &Option<PathBuf>, model: &ModelArgs,
+    sources: &SequenceSources, accuracy: f32, trace_path: &Option<PathBuf>) -> CliResult
+    where T: PrimInt + Serialize + DeserializeOwned + Sync + Send {
+    let sequence = Sequence::<T>::open(frames)?;
+    if let (Some((w, h)), Some(frame)) = (model.size, sequence.frames.first()) {
+        if (frame.width, frame.height) != (w, h) {
+            let msg = format!("Frames are {}x{}, expected {}x{}.", frame.width, frame.height, w, h);
+            return Err(Box::new(CliError(msg)));
+        }
+    }
 
-        for t in &ts {
-            let i = t.apply_to(&int);
-            cs.learn(t, &i, int.clone());
+    let mut cs = match cs_path {
+        Some(path) => load_context_space::<T>(path)?,
+        None => ContextSpace::<T>::new(),
+    };
+
+    let sidecar = sources.transformations.clone().or_else(|| Sequence::<T>::find_sidecar(frames));
+    let given;
+    let candidates;
+    let transformations = match (sidecar, sources.candidates) {
+        (Some(path), _) => {
+            given = sequence.load_sidecar(&path)?;
+            println!("Transformations of the frames are read from {}.", path.display());
+            SequenceTransformations::Given(&given)
+        },
+        (None, Some(path)) => {
+            candidates = diff_context_space::load_transformations(path)?;
+            SequenceTransformations::Inferred { candidates: &candidates, min_similarity: sources.min_similarity }
+        },
+        (None, None) => {
+            let msg = String::from("No transformations of the frames: give a sidecar file or the candidates to infer them.");
+            return Err(Box::new(CliError(msg)));
+        },
+    };
+
+    let trace = sequence.learn(&mut cs, &transformations, accuracy)?;
+    for record in &trace {
+        let recognized = record.recognized.as_ref().map(|r| r.name()).unwrap_or("-");
+        match &record.tran {
+            Some(t) => println!("{}: recognized: {}, learnt with t: {}.", record.name, recognized, t),
+            None => println!("{}: recognized: {}, not learnt.", record.name, recognized),
         }
     }
 
-    println!("Learning took {} seconds.", now.elapsed().unwrap().as_secs());
+    if let Some(path) = trace_path {
+        serde_json::to_writer_pretty(File::create(path)?, &trace)?;
+    }
 
-    let all_count = cs.contexts.len();
-    let active_count = cs.contexts.iter().filter(|c| c.rules.len() > 0).count();
-    println!("All contexts count: {}, active: {}, transformations count: {}.", all_count, active_count, &ts.len());
+    cs.save(out)?;
+    Ok(())
+}
 
-    // print_contexts(cs.contexts.iter());
+#[allow(clippy::too_many_arguments)]
+fn interpret<T>(cs_path: &Path, image: &Path, model: &ModelArgs, accuracy: f32, tran: &TransformationArgs,
+    resampling: &ResamplingArgs, mapped: bool, json: bool) -> CliResult
+    where T: PrimInt + Serialize + DeserializeOwned + Sync + Send {
+    let int = load_information::<T>(image, model)?;
+    let i = tran.to_transformation().apply_with(&int, &resampling.to_resampling());
+
+    let result = if mapped {
+        let now = std::time::SystemTime::now();
+        let cs = MappedContextSpace::<T>::open(cs_path)?;
+        println!("Mapping context space took {} ms.", now.elapsed()?.as_millis());
+        cs.interpret(&i, accuracy)
+    } else {
+        load_context_space::<T>(cs_path)?.interpret(&i, accuracy)
+    };
+
+    match result {
+        None => println!("Interpretation could not been found."),
+        Some(found) if json => println!("{}", serde_json::to_string(&found)?),
+        Some(found) => println!("{}.", found),
+    }
 
-    let now = std::time::SystemTime::now();
+    Ok(())
+}
 
-    let mut out_cs_path = PathBuf::from(env_path);
-    out_cs_path.push("files/out/context_spaces/cs_64x64_mono_icon_set.bin");
-    cs.save(out_cs_path).unwrap();
+#[allow(clippy::too_many_arguments)]
+fn interpret_top_k<T>(cs_path: &Path, image: &Path, model: &ModelArgs, accuracy: f32, tran: &TransformationArgs,
+    resampling: &ResamplingArgs, k: usize, json: bool) -> CliResult
+    where T: PrimInt + Serialize + DeserializeOwned + Sync + Send {
+    let int = load_information::<T>(image, model)?;
+    let i = tran.to_transformation().apply_with(&int, &resampling.to_resampling());
 
-    println!("Saving of the context space file took {} seconds.", now.elapsed().unwrap().as_secs());
+    let candidates = load_context_space::<T>(cs_path)?.interpret_top_k(&i, accuracy, k);
+    if candidates.is_empty() {
+        println!("Interpretation could not been found.");
+    }
+    if json {
+        println!("{}", serde_json::to_string(&candidates)?);
+        return Ok(());
+    }
+    for (n, c) in candidates.iter().enumerate() {
+        println!("{}. {}.", n + 1, c);
+    }
+
+    Ok(())
 }
 
-fn print_contexts<'a, I, T>(contexts: I)
-    where T: 'a + PrimInt + Serialize,
-    I: Iterator<Item = &'a Context<T>>, {
+/// Files eval writes the report to.
+struct EvalOutputs<'a> {
+    json: &'a Option<PathBuf>,
+    csv: &'a Option<PathBuf>,
+    confusion: &'a Option<PathBuf>,
+}
 
-    let contexts_to_print = contexts.filter(|c| c.rules.len() > 0);
+#[allow(clippy::too_many_arguments)]
+fn eval<T>(cs_path: &Path, images: &Path, transformations: &Path, outputs: &EvalOutputs,
+    model: &ModelArgs, accuracy: f32, learn: bool, no_rotation: bool, resampling: &ResamplingArgs) -> CliResult
+    where T: PrimInt + Serialize + DeserializeOwned + Sync + Send {
+    let mut cs = load_context_space::<T>(cs_path)?;
+    let resampling = resampling.to_resampling();
 
-    let env_path = env!("CARGO_MANIFEST_DIR");
-    let mut out_rules_path = PathBuf::from(env_path);
-    out_rules_path.push("files/out/contexts/");
+    let now = std::time::SystemTime::now();
 
-    let mut full_out_path = PathBuf::from(&out_rules_path);
-    full_out_path.push("all_contexts_rules.gif");
-    let file_buff = File::create(full_out_path).expect("Unable to create the output file.");
-    let mut gif_enc = image::gif::GifEncoder::new(file_buff);
+    let ts = load_transformations(transformations, no_rotation)?;
 
-    let mut active_count = 0;
+    println!("N of transformations: {}", ts.len());
 
-    for c in contexts_to_print {
-        active_count = active_count + 1;
-        for r_idx in 0..c.rules.len() {
-            let img = c.rules[r_idx].to_image();
-            let img_frame = image::Frame::new(img.to_rgba());
-            gif_enc.encode_frame(img_frame).unwrap();
+    println!("Loading transformations took {} seconds.", now.elapsed()?.as_secs());
+
+    let mut report = EvalReport::new();
+    for img_path in image_paths(images)? {
+        let int = load_information::<T>(&img_path, model)?;
+
+        if learn {
+            // teach context space with new image, no transformation
+            let t = Transformation::new(0, 0, 0.0);
+            cs.learn(&t, &int, int.clone())?;
+        }
+
+        report.add_information(&cs, &int, &ts, accuracy, &resampling);
+    }
+
+    let metrics = report.metrics();
+    println!("samples: {}, int acc: {}, t acc: {}, coverage: {}, mean dx: {}, mean dy: {}, mean da: {}.",
+        metrics.samples,
+        metrics.interpretation_accuracy,
+        metrics.transformation_accuracy,
+        metrics.coverage,
+        metrics.mean_dx,
+        metrics.mean_dy,
+        metrics.mean_da);
+
+    if let Some(path) = outputs.json {
+        report.write_json(File::create(path)?)?;
+    }
+    if let Some(path) = outputs.csv {
+        report.write_csv(File::create(path)?)?;
+    }
+    if let Some(path) = outputs.confusion {
+        report.write_confusion_csv(File::create(path)?)?;
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn cross_validate<T>(images: &[PathBuf], transformations: &Path, k: usize, seed: u64, holdout: Holdout,
+    json: &Option<PathBuf>, model: &ModelArgs, accuracy: f32, no_rotation: bool, resampling: &ResamplingArgs) -> CliResult
+    where T: PrimInt + Serialize + DeserializeOwned + Sync + Send {
+    let dataset = Dataset::<T>::open(images)?;
+    if let Some((w, h)) = model.size {
+        if let Some(int) = dataset.ints.iter().find(|int| int.width != w || int.height != h) {
+            let msg = format!("Image {} is {}x{}, expected {}x{}.", int.name, int.width, int.height, w, h);
+            return Err(Box::new(CliError(msg)));
         }
     }
+    let ts = load_transformations(transformations, no_rotation)?;
+    println!("N of images: {}, N of transformations: {}", dataset.len(), ts.len());
+
+    let reports = dataset.cross_validate(&ContextSpace::new(), &ts, k, seed, holdout, accuracy, &resampling.to_resampling())?;
+
+    let mut folds = Vec::with_capacity(reports.len());
+    for (n, report) in reports.iter().enumerate() {
+        let metrics = report.metrics();
+        println!("fold: {}, samples: {}, int acc: {}, t acc: {}, coverage: {}.",
+            n,
+            metrics.samples,
+            metrics.interpretation_accuracy,
+            metrics.transformation_accuracy,
+            metrics.coverage);
+        folds.push(metrics);
+    }
+
+    if let Some(path) = json {
+        serde_json::to_writer_pretty(File::create(path)?, &folds)?;
+    }
+
+    Ok(())
 }
 
-fn load_transformations(path: &PathBuf) -> Vec<Transformation> {
-    let f = File::open(path)
-                .expect("Unable to open the file.");
+fn dump_rules<T>(cs_path: &Path, out: &Path) -> CliResult
+    where T: PrimInt + Serialize + DeserializeOwned + Sync + Send {
+    let cs = load_context_space::<T>(cs_path)?;
+    let (width, height) = match cs.interpretations.first() {
+        Some(int) => (int.width, int.height),
+        None => return Ok(()),
+    };
+    print_contexts(cs.contexts.iter(), width, height, out)
+}
 
-    let transformations = bincode::deserialize_from(f)
-                                .expect("Unable to read binary transformations from the file.");
-    transformations
+fn memory<T>(cs_path: &Path) -> CliResult
+    where T: PrimInt + Serialize + DeserializeOwned + Sync + Send {
+    let cs = load_context_space::<T>(cs_path)?;
+    println!("{}", cs.memory_usage());
+    Ok(())
 }
 
-fn transform_and_save_image() {
-    let env_path = env!("CARGO_MANIFEST_DIR");
-    
-    let mut orig_img_path = PathBuf::from(env_path);
-    orig_img_path.push("files/interpretations/32x32/galaxy/1.png");
+fn save_mapped<T>(cs_path: &Path, out: &Path) -> CliResult
+    where T: PrimInt + Serialize + DeserializeOwned + Sync + Send {
+    let cs = load_context_space::<T>(cs_path)?;
+    cs.save_mapped(out)?;
+    Ok(())
+}
+
+fn info(cs_path: &Path) -> CliResult {
+    let header = ContextSpace::<u8>::read_header(cs_path)?;
+    println!("{}", header);
+    Ok(())
+}
 
-    let img = image::open(orig_img_path).unwrap();
-    let int = Information::<u32>::from_image(&img, String::from("1.png"));
+fn migrate<T>(input: &Path, out: &Path) -> CliResult
+    where T: PrimInt + Serialize + DeserializeOwned + Sync + Send {
+    let cs = ContextSpace::<T>::load_legacy(input)?;
+    cs.save(out)?;
 
-    let t = Transformation { x: -5, y: 5, a: 0.0 };
-    let i = t.apply_to(&int);
+    println!("Migrated {} contexts and {} interpretations.", cs.contexts.len(), cs.interpretations.len());
+    Ok(())
+}
+
+fn render_transformation<T>(image: &Path, out: &Path, model: &ModelArgs, tran: &TransformationArgs,
+    resampling: &ResamplingArgs) -> CliResult
+    where T: PrimInt + Serialize {
+    let int = load_information::<T>(image, model)?;
+
+    let i = tran.to_transformation().apply_with(&int, &resampling.to_resampling());
     let out_img = i.to_image();
 
-    let mut out_img_path = PathBuf::from(env_path);
-    out_img_path.push("files/out/images/1.png");
+    out_img.save_with_format(out, image::ImageFormat::Png)?;
+    Ok(())
+}
 
-    out_img.save_with_format(out_img_path, image::ImageFormat::Png).unwrap();
+fn print_contexts<'a, I, T>(contexts: I, width: u32, height: u32, out: &Path) -> CliResult
+    where T: 'a + PrimInt + Serialize,
+    I: Iterator<Item = &'a Context<T>>, {
+
+    let contexts_to_print = contexts.filter(|c| !c.rules.is_empty());
+
+    let file_buff = File::create(out)?;
+    let mut gif_enc = image::gif::GifEncoder::new(file_buff);
+
+    for c in contexts_to_print {
+        for r in &c.rules {
+            let img = r.to_image(width, height);
+            let img_frame = image::Frame::new(img.to_rgba8());
+            gif_enc.encode_frame(img_frame)?;
+        }
+    }
+
+    Ok(())
 }
 
-fn print_transformations() {
-    let env_path = env!("CARGO_MANIFEST_DIR");
+fn load_context_space<T>(path: &Path) -> Result<ContextSpace<T>, Box<dyn std::error::Error>>
+    where T: PrimInt + Serialize + DeserializeOwned + Sync + Send {
+    let now = std::time::SystemTime::now();
+
+    let cs = ContextSpace::<T>::load(path)?;
 
-    let mut ts_path = PathBuf::from(env_path);
-    ts_path.push("files/transformations/t_64x64.bin");
-    let ts = load_transformations(&ts_path);
+    println!("Loading context space took {} seconds.", now.elapsed()?.as_secs());
+    Ok(cs)
+}
 
-    let mut log_path = PathBuf::from(env_path);
-    log_path.push("files/out/logs/t_log.log");
+/// Reads an image as information checking it has the expected size.
+fn load_information<T>(path: &Path, model: &ModelArgs) -> Result<Information<T>, Box<dyn std::error::Error>>
+    where T: PrimInt + Serialize {
+    let img = image::open(path)?;
+    if let Some((w, h)) = model.size {
+        if img.width() != w || img.height() != h {
+            let msg = format!("Image {} is {}x{}, expected {}x{}.",
+                path.display(), img.width(), img.height(), w, h);
+            return Err(Box::new(CliError(msg)));
+        }
+    }
 
-    let mut log_file = File::create(log_path).unwrap();
+    let name = path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let int = Information::<T>::from_image(&img, name)?;
+    Ok(int)
+}
 
-    for t in ts {
-        writeln!(&mut log_file, "{}", t);
+fn load_transformations(path: &Path, no_rotation: bool) -> Result<Vec<Transformation>, Box<dyn std::error::Error>> {
+    let mut transformations = diff_context_space::load_transformations(path)?;
+    if no_rotation {
+        transformations.retain(|t| t.a == 0.0);
     }
-}
\ No newline at end of file
+    Ok(transformations)
+}
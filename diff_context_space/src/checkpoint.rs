@@ -0,0 +1,353 @@
+//! Checkpoints of the training.
+//! A checkpoint folder keeps the base snapshot of the context space
+//! and the log of the records appended after it. Every record has the
+//! (image, transformation) pairs learnt since the previous record,
+//! the changed contexts and the new interpretations, so saving does not
+//! rewrite the whole model. When the log gets long it is compacted into
+//! a new base snapshot. A generation n of the checkpoint is the pair of
+//! files base_n.bin and log_n.bin, the previous generation is removed
+//! only after the next one is completely written.
+//!
+//! Every record starts with its length and checksum (u64, little-endian).
+//! A record which was not completely written because of a crash
+//! is dropped on resume.
+
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::{ Information, Context, ContextSpace, Transformation };
+use crate::format;
+use crate::error::{Error, Result};
+
+extern crate num_traits;
+use num_traits::int::PrimInt;
+
+extern crate serde;
+use serde::{Serialize, Deserialize};
+
+const RECORD_HEADER_SIZE: usize = 16;
+
+/// Progress of the training saved in a folder.
+pub struct Checkpoint {
+    dir: PathBuf,
+    generation: u64,
+    /// Number of the log records after which the log is compacted
+    /// into a new base snapshot.
+    pub max_log_records: usize,
+    log_records: usize,
+    done: HashSet<(String, Transformation)>,
+    pending: Vec<(String, Transformation)>,
+    interpretations_saved: usize,
+}
+
+#[derive(Serialize)]
+struct RecordRef<'a, T: PrimInt + Serialize> {
+    done: &'a [(String, Transformation)],
+    samples_count: u64,
+    contexts: Vec<(u32, &'a Context<T>)>,
+    interpretations: &'a [Information<T>],
+}
+
+#[derive(Deserialize)]
+struct Record<T: PrimInt + Serialize> {
+    done: Vec<(String, Transformation)>,
+    samples_count: u64,
+    contexts: Vec<(u32, Context<T>)>,
+    interpretations: Vec<Information<T>>,
+}
+
+impl Checkpoint {
+    /// Restores the context space from the latest generation of the checkpoint
+    /// in dir. If there is no checkpoint yet, it is started with the given
+    /// context space, otherwise the given one is dropped. Its settings must be
+    /// the same as the ones the checkpoint was started with, IncompatibleModel
+    /// is returned otherwise, so the training is not continued with other settings.
+    pub fn resume<T, P: AsRef<Path>>(dir: P, given: ContextSpace<T>) -> Result<(Checkpoint, ContextSpace<T>)>
+        where T: PrimInt + Sync + Send + Serialize + for<'de> Deserialize<'de> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut cp = Checkpoint {
+            dir,
+            generation: 0,
+            max_log_records: 64,
+            log_records: 0,
+            done: HashSet::new(),
+            pending: Vec::new(),
+            interpretations_saved: 0,
+        };
+
+        let generation = match cp.latest_generation()? {
+            None => {
+                let mut cs = given;
+                cp.compact(&mut cs)?;
+                return Ok((cp, cs));
+            },
+            Some(generation) => generation,
+        };
+        cp.generation = generation;
+
+        let mut cs = ContextSpace::<T>::load(cp.base_path(generation))?;
+        if let Some(diff) = cs.settings_difference(&given) {
+            let msg = format!("the checkpoint was started with other settings: {}", diff);
+            return Err(Error::IncompatibleModel(msg));
+        }
+
+        let log_path = cp.log_path(generation);
+        let bytes = fs::read(&log_path)?;
+        let mut pos = 0;
+        while let Some((payload, next)) = read_record(&bytes, pos) {
+            let record: Record<T> = bincode::deserialize(payload)?;
+            cp.apply(record, &mut cs)?;
+            cp.log_records += 1;
+            pos = next;
+        }
+
+        // drop the tail of the record which was being written on a crash
+        if pos < bytes.len() {
+            OpenOptions::new().write(true).open(&log_path)?.set_len(pos as u64)?;
+        }
+
+        cs.take_changed();
+        cp.interpretations_saved = cs.interpretations.len();
+        Ok((cp, cs))
+    }
+
+    /// Tells if the transformation was already learnt for the image.
+    /// The pairs are kept by the transformation itself, not by its index,
+    /// so the training may be resumed with another list of the transformations.
+    pub fn is_done(&self, image: &str, t: &Transformation) -> bool {
+        self.done.contains(&(image.to_string(), t.clone()))
+    }
+
+    /// Number of the learnt pairs including the ones not saved yet.
+    pub fn done_count(&self) -> usize {
+        self.done.len() + self.pending.len()
+    }
+
+    /// Remembers that the pair is learnt, it is saved with the next record.
+    pub fn mark_done(&mut self, image: String, t: Transformation) {
+        self.pending.push((image, t));
+    }
+
+    /// Appends the changes of the context space made since the previous save
+    /// to the log or compacts the log if it is long enough.
+    pub fn save<T>(&mut self, cs: &mut ContextSpace<T>) -> Result<()>
+        where T: PrimInt + Sync + Send + Serialize {
+        if self.log_records >= self.max_log_records {
+            return self.compact(cs);
+        }
+
+        let changed = cs.take_changed();
+        let record = RecordRef {
+            done: &self.pending,
+            samples_count: cs.samples_count as u64,
+            contexts: changed.iter().map(|idx| (*idx as u32, &cs.contexts[*idx])).collect(),
+            interpretations: &cs.interpretations[self.interpretations_saved..],
+        };
+
+        let mut f = OpenOptions::new().append(true).open(self.log_path(self.generation))?;
+        f.write_all(&frame(&record)?)?;
+        f.sync_data()?;
+
+        self.log_records += 1;
+        self.done.extend(self.pending.drain(..));
+        self.interpretations_saved = cs.interpretations.len();
+        Ok(())
+    }
+
+    /// Writes the whole context space as the new base snapshot
+    /// and starts a new log with all learnt pairs.
+    pub fn compact<T>(&mut self, cs: &mut ContextSpace<T>) -> Result<()>
+        where T: PrimInt + Sync + Send + Serialize {
+        let next = self.generation + 1;
+
+        cs.take_changed();
+        self.done.extend(self.pending.drain(..));
+        let mut done: Vec<(String, Transformation)> = self.done.iter().cloned().collect();
+        done.sort_by(|a, b| sample_key(a).cmp(&sample_key(b)));
+
+        let record = RecordRef::<T> {
+            done: &done,
+            samples_count: cs.samples_count as u64,
+            contexts: Vec::new(),
+            interpretations: &[],
+        };
+
+        let base_tmp = self.dir.join("base.tmp");
+        cs.save(&base_tmp)?;
+        fs::rename(&base_tmp, self.base_path(next))?;
+
+        let log_tmp = self.dir.join("log.tmp");
+        let mut f = fs::File::create(&log_tmp)?;
+        f.write_all(&frame(&record)?)?;
+        f.sync_data()?;
+        fs::rename(&log_tmp, self.log_path(next))?;
+
+        for path in &[self.base_path(self.generation), self.log_path(self.generation)] {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+
+        self.generation = next;
+        self.log_records = 1;
+        self.interpretations_saved = cs.interpretations.len();
+        Ok(())
+    }
+
+    fn apply<T>(&mut self, record: Record<T>, cs: &mut ContextSpace<T>) -> Result<()>
+        where T: PrimInt + Sync + Send + Serialize {
+        for (idx, c) in record.contexts {
+            let idx = idx as usize;
+            if idx < cs.contexts.len() {
+                cs.contexts[idx] = c;
+            } else if idx == cs.contexts.len() {
+                cs.contexts.push(c);
+            } else {
+                return Err(Error::CorruptModel(format!("checkpoint record skips the contexts before {}", idx)));
+            }
+        }
+        cs.interpretations.extend(record.interpretations);
+        cs.samples_count = record.samples_count as usize;
+        self.done.extend(record.done);
+        Ok(())
+    }
+
+    /// The latest generation which has both the base and the log.
+    fn latest_generation(&self) -> Result<Option<u64>> {
+        let mut latest = None;
+        for dr in fs::read_dir(&self.dir)? {
+            let name = dr?.file_name();
+            let generation = name.to_str()
+                .and_then(|name| name.strip_prefix("base_"))
+                .and_then(|name| name.strip_suffix(".bin"))
+                .and_then(|n| n.parse::<u64>().ok());
+
+            if let Some(generation) = generation {
+                if self.log_path(generation).exists() && latest < Some(generation) {
+                    latest = Some(generation);
+                }
+            }
+        }
+        Ok(latest)
+    }
+
+    fn base_path(&self, generation: u64) -> PathBuf {
+        self.dir.join(format!("base_{}.bin", generation))
+    }
+
+    fn log_path(&self, generation: u64) -> PathBuf {
+        self.dir.join(format!("log_{}.bin", generation))
+    }
+}
+
+/// The order of the learnt pairs in the compacted log, the transformations
+/// are compared by the same bins as their equality.
+fn sample_key((image, t): &(String, Transformation)) -> (&str, i16, i16, u32, i64, bool, bool) {
+    (image, t.x, t.y, t.angle_bin(), t.scale_bin(), t.flip_h, t.flip_v)
+}
+
+/// Serializes the record with its length and checksum in front.
+fn frame<T: PrimInt + Serialize>(record: &RecordRef<T>) -> Result<Vec<u8>> {
+    let payload = bincode::serialize(record)?;
+    let mut bytes = Vec::with_capacity(RECORD_HEADER_SIZE + payload.len());
+    bytes.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&format::checksum(&payload).to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    Ok(bytes)
+}
+
+/// Returns the payload of the record at pos and the position of the next one,
+/// None if the record is incomplete or damaged.
+fn read_record(bytes: &[u8], pos: usize) -> Option<(&[u8], usize)> {
+    let header = bytes.get(pos..pos + RECORD_HEADER_SIZE)?;
+    let mut len = [0u8; 8];
+    len.copy_from_slice(&header[..8]);
+    let mut checksum = [0u8; 8];
+    checksum.copy_from_slice(&header[8..]);
+
+    let start = pos + RECORD_HEADER_SIZE;
+    let end = start.checked_add(u64::from_le_bytes(len) as usize)?;
+    let payload = bytes.get(start..end)?;
+    if format::checksum(payload) != u64::from_le_bytes(checksum) {
+        return None;
+    }
+    Some((payload, end))
+}
+
+#[test]
+fn can_resume_training() {
+    use crate::Transformation;
+
+    let mut ints = Vec::new();
+    for (n, y) in [1u32, 3, 5].iter().enumerate() {
+        let mut int = Information::<u8>::new(12, 8, format!("line_{}", n));
+        for x in 2..9 {
+            int.set(x, *y, true);
+        }
+        ints.push(int);
+    }
+    let ts = [Transformation::new(1, 0, 0.0), Transformation::new(0, -1, 0.0)];
+
+    let dir = std::env::temp_dir().join(format!("diff_context_space_checkpoint_{}", std::process::id()));
+    if dir.exists() {
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    let learn = |cs: &mut ContextSpace<u8>, cp: Option<&mut Checkpoint>, int: &Information<u8>, t: &Transformation| {
+        cs.learn(t, &t.apply_to(int), int.clone()).unwrap();
+        if let Some(cp) = cp {
+            cp.mark_done(int.name.clone(), t.clone());
+            cp.save(cs).unwrap();
+        }
+    };
+
+    let mut expected = ContextSpace::<u8>::new();
+    for int in &ints {
+        for t in &ts {
+            learn(&mut expected, None, int, t);
+        }
+    }
+
+    // learn the first image and a half with compacting after every 2 records
+    let (mut cp, mut cs) = Checkpoint::resume(&dir, ContextSpace::<u8>::new()).unwrap();
+    cp.max_log_records = 2;
+    learn(&mut cs, Some(&mut cp), &ints[0], &ts[0]);
+    learn(&mut cs, Some(&mut cp), &ints[0], &ts[1]);
+    learn(&mut cs, Some(&mut cp), &ints[1], &ts[0]);
+    drop(cs);
+
+    // a record cut by the crash
+    let mut log = OpenOptions::new().append(true).open(cp.log_path(cp.generation)).unwrap();
+    log.write_all(&[42, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3]).unwrap();
+    drop(log);
+
+    let (mut cp, mut cs) = Checkpoint::resume(&dir, ContextSpace::<u8>::new()).unwrap();
+    assert_eq!(cp.done_count(), 3);
+    // the pairs are found by the transformations whatever list they are in
+    assert!(cp.is_done(&ints[0].name, &Transformation::new(1, 0, 2.0 * std::f32::consts::PI)));
+    assert!(!cp.is_done(&ints[1].name, &ts[1]));
+    for int in &ints {
+        for t in &ts {
+            if !cp.is_done(&int.name, t) {
+                learn(&mut cs, Some(&mut cp), int, t);
+            }
+        }
+    }
+
+    assert_eq!(cs.samples_count, expected.samples_count);
+    assert_eq!(bincode::serialize(&cs).unwrap(), bincode::serialize(&expected).unwrap());
+    drop(cp);
+
+    // the training is not continued with other settings
+    let mut other = ContextSpace::<u8>::new();
+    other.learning_radius = 2.0;
+    match Checkpoint::resume(&dir, other) {
+        Err(Error::IncompatibleModel(msg)) => assert!(msg.contains("learning radius")),
+        _ => panic!("resumed with another learning radius"),
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
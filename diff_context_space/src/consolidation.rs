@@ -0,0 +1,33 @@
+extern crate serde;
+use serde::{Serialize, Deserialize};
+
+/// The policy used to consolidate the temporary memory of the rules.
+/// Every rule counts how many samples it has learnt (support)
+/// and how many times every bit of the interpretation was set (hits).
+/// On consolidation the interpretation of the rule is rebuilt
+/// from these statistics.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConsolidationPolicy {
+    /// Rules learnt from fewer samples are not consolidated yet.
+    pub min_support: f32,
+    /// Part of the samples where a bit had to be set
+    /// to stay in the interpretation of the rule.
+    pub min_hit_ratio: f32,
+    /// Number of the latest samples the statistics represents.
+    /// Older samples fade out proportionally. None keeps all samples.
+    pub decay_window: Option<f32>,
+}
+
+impl Default for ConsolidationPolicy {
+    fn default() -> Self {
+        ConsolidationPolicy { min_support: 3.0, min_hit_ratio: 0.75, decay_window: None }
+    }
+}
+
+/// Tells the context space to consolidate all contexts
+/// after every `every` learnt samples.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConsolidationSchedule {
+    pub every: usize,
+    pub policy: ConsolidationPolicy,
+}
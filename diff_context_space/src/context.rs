@@ -1,6 +1,9 @@
 use crate::{ Transformation, Information };
-use crate::rule::Rule;
+use crate::rule::{Rule, RuleMode, Consolidated};
+use crate::consolidation::ConsolidationPolicy;
 use std::fmt;
+use std::collections::HashMap;
+use std::mem;
 
 extern crate num_traits;
 use num_traits::int::PrimInt;
@@ -24,27 +27,91 @@ use serde::{Serialize, Deserialize};
 /// is received: I_new_int. This piece of information is compared to
 /// the shared between all context memory. If there is a match than 
 /// we say that some interpretation is found.
+/// The rules are indexed by the offset of the bit they are set for
+/// (see Information::ones()), so learning and interpretation cost
+/// is proportional to the number of set bits only.
+/// The index is not saved, it is rebuilt on deserialization.
 #[derive(Serialize, Deserialize)]
+#[serde(from = "ContextData<T>")]
 pub struct Context<T: PrimInt + Serialize> {
     pub tran: Transformation,
-    pub rules: Vec<Rule<T>>
+    pub rules: Vec<Rule<T>>,
+    pub mode: RuleMode,
+    #[serde(skip)]
+    index: HashMap<u32, usize>
+}
+
+/// Serialized part of the context.
+#[derive(Deserialize)]
+struct ContextData<T: PrimInt + Serialize> {
+    tran: Transformation,
+    rules: Vec<Rule<T>>,
+    mode: RuleMode
+}
+
+impl<T: PrimInt + Serialize> From<ContextData<T>> for Context<T> {
+    fn from(data: ContextData<T>) -> Self {
+        let mut c = Context { tran: data.tran, rules: data.rules, mode: data.mode, index: HashMap::new() };
+        c.rebuild_index();
+        c
+    }
 }
 
 impl<T:PrimInt + Serialize> Context<T> {
     /// Creates context with xya transformation.
     pub fn new(x: i16, y: i16, a: f32) -> Context<T> {
-        let t = Transformation { x: x, y: y, a: a };
+        let t = Transformation::new(x, y, a);
         Context::with_transformation(t)
     }
 
     pub fn with_transformation(t: Transformation) -> Context<T> {
-        let c = Context { tran: t, rules: Vec::new() };
-        c
+        Context::with_mode(t, RuleMode::default())
+    }
+
+    /// Creates context which rules build interpretations with the given mode.
+    pub fn with_mode(t: Transformation, mode: RuleMode) -> Context<T> {
+        Context { tran: t, rules: Vec::new(), mode, index: HashMap::new() }
+    }
+
+    /// Creates context with the already learnt rules.
+    pub fn with_rules(t: Transformation, mode: RuleMode, rules: Vec<Rule<T>>) -> Context<T> {
+        Context::from(ContextData { tran: t, rules, mode })
+    }
+
+    /// Maps the bit offset of every rule information to the rule position.
+    fn rebuild_index(&mut self) {
+        self.index = self.rules.iter()
+            .enumerate()
+            .map(|(idx, r)| (r.src, idx))
+            .collect();
+    }
+
+    /// Approximate number of bytes the rules index takes in memory.
+    pub fn index_memory_size(&self) -> usize {
+        // a hash map keeps a control byte per bucket besides the entry
+        self.index.capacity() * (mem::size_of::<(u32, usize)>() + 1)
+    }
+
+    /// Returns the rule learnt for the bit with the given offset.
+    pub fn rule_for(&self, offset: u32) -> Option<&Rule<T>> {
+        self.index.get(&offset).map(|idx| &self.rules[*idx])
     }
 
     /// This method remembers the pair i(nformation) and 
     /// its int(erpretation) for the context.
     pub fn learn(&mut self, i: &Information<T>, int: &Information<T>) {
+        self.learn_weighted(i, int, 1.0);
+    }
+
+    /// The same as learn, but the pair counts as the weight part of a sample,
+    /// see Rule::learn_weighted.
+    pub fn learn_weighted(&mut self, i: &Information<T>, int: &Information<T>, weight: f32) {
+        self.learn_with_hits(i, int, weight, true);
+    }
+
+    /// The same as learn_weighted, but the rules of the Intersection mode
+    /// count the hits only if keep_hits is set, see Rule::hits.
+    pub fn learn_with_hits(&mut self, i: &Information<T>, int: &Information<T>, weight: f32, keep_hits: bool) {
         // for every set bit in the information i we need to remember the 
         // interpretation int with adding it to the already existing one.
         // This is done in the recursive way: 
@@ -53,101 +120,86 @@ impl<T:PrimInt + Serialize> Context<T> {
         // int by the given bits from i. It is easier to go for every bit in 
         // the given i and summarize data for int getting the resulting interpretation.
 
-        let bits_count = T::zero().count_zeros();
-        
-        for data_idx in 0..i.data.len() {
-            let mut mask = T::one(); // kind of 0000 0001 depending on the type
-            for _ in 0..bits_count {
-                // Check if incoming information has the bit set.
-                if i.data[data_idx] & mask != T::zero() {
-                    // We know that for now the rules have only one bit set 
-                    // for the information
-                    // that is why we do not need to compare anything except this 
-                    // bit in the particular place via the mask. Later on this logic can be 
-                    // essentially improved.
-                    let rule_pos = self.rules.iter().position(|r| r.i.data[data_idx] == mask);
-                    match rule_pos {
-                        Some(rule_idx) => {
-                            // Improve the existing rule by adding new interpretation 
-                            // to the existing one with & operator. The more examples 
-                            // the system gets the cleaner rule of the bit interpretation
-                            // is received.
-                            let rule = &self.rules[rule_idx];
-                            let int_data_len = rule.int.data.len();
-                            let mut new_int_data = Vec::<T>::with_capacity(int_data_len);
-                            for idx in 0..int_data_len {
-                                let new_d = rule.int.data[idx] & int.data[idx];
-                                new_int_data.push(new_d);
-                            }
-
-                            let new_int = Information{ data: new_int_data, name: String::from("") };
-                            let new_rule = Rule::new(&rule.i, &new_int);
-                            
-                            // update the rule
-                            self.rules[rule_idx] = new_rule;
-                        },
-                        None => {
-                            // add new rule
-                            let mut i_data = vec!(T::zero(); i.data.len());
-                            i_data[data_idx] = mask.clone();
-                            let new_i = Information { data: i_data, name: String::from("") };
-                            let new_rule = Rule::new(&new_i, &int);
-                            self.rules.push(new_rule);
-                        }
-                    };
+        for offset in i.ones() {
+            // We know that for now the rules have only one bit set 
+            // for the information
+            // that is why we do not need to compare anything except this 
+            // bit which offset is the key of the index.
+            match self.index.get(&offset) {
+                Some(rule_idx) => {
+                    // Improve the existing rule by adding new interpretation 
+                    // to the existing one with & operator or by counting. The more 
+                    // examples the system gets the cleaner rule of the bit interpretation
+                    // is received.
+                    self.rules[*rule_idx].learn_with_hits(int, self.mode, weight, keep_hits);
+                },
+                None => {
+                    // add new rule
+                    let new_rule = Rule::learnt(offset, int, weight, keep_hits || self.mode.counts_hits());
+                    self.index.insert(offset, self.rules.len());
+                    self.rules.push(new_rule);
                 }
+            };
+        }
+    }
 
-                // shift left on one position
-                mask = mask.unsigned_shl(1);
-            }
+    /// Consolidates the temporary memory of every rule
+    /// and removes the rules which do not keep any bit anymore.
+    /// Returns true if any rule changed or was removed.
+    pub fn consolidate(&mut self, policy: &ConsolidationPolicy) -> bool {
+        let mode = self.mode;
+        let len = self.rules.len();
+        let mut changed = false;
+        self.rules.retain_mut(|r| match r.consolidate(policy, mode) {
+            Consolidated::Unchanged => true,
+            Consolidated::Changed => {
+                changed = true;
+                true
+            },
+            Consolidated::Emptied => false,
+        });
+        if self.rules.len() != len {
+            self.rebuild_index();
+            changed = true;
         }
+        changed
     }
 
     /// Applies all rules the context has to the incoming information i.
+    /// Returns the interpretation and its confidence: the part of the set bits
+    /// of i which have a rule weighted by the confidence of the rules.
     pub fn interpret(&self, i: &Information<T>) -> Option<(Information<T>, f32)> {
         // on interpretation we look at every bit in the 
         // input information i and try to find a rule with the same bit set for information i as well
         // then combine all found rules interpretations int into one
         
-        if self.rules.len() == 0 {
+        if self.rules.is_empty() {
             return None;
         }
 
-        let bit_length = T::zero().count_zeros();
+        let bits = Information::<T>::word_bits();
         let mut d_int = vec!(T::zero(); i.data.len());
 
-        // these two variables are used for the accuracy calculation
+        // these variables are used for the accuracy calculation
         let mut bits_count = 0;
         let mut match_rules_count = 0;
-
-        for data_idx in 0..i.data.len() {
-            let mut mask = T::one();
-
-            for _ in 0..bit_length {
-                // check if information has the bit set
-                if i.data[data_idx] & mask != T::zero() {
-                    bits_count = bits_count + 1;
-
-                    // try to find the transformation rule with the same bit set
-                    let rule = self.rules.iter().find(|r| r.i.data[data_idx] == mask);
-
-                    match rule {
-                        Some(r) => {
-                            // combine the rule interpretations into one via OR
-                            for i in 0..r.int.data.len() {
-                                d_int[i] = d_int[i] | r.int.data[i];
-                            }
-
-                            match_rules_count = match_rules_count + 1;
-                        },
-                        None => {
-                            // do nothing
-                        }
+        let mut confidence = 0.0;
+
+        for offset in i.ones() {
+            bits_count += 1;
+
+            // try to find the transformation rule with the same bit set
+            if let Some(r) = self.rule_for(offset) {
+                // combine the rule interpretations into one via OR,
+                // the bits out of i are skipped in case it has another size
+                for offset in &r.int {
+                    if let Some(d) = d_int.get_mut((offset / bits) as usize) {
+                        *d = *d | T::one().unsigned_shl(offset % bits);
                     }
                 }
 
-                // shift left on one position
-                mask = mask.unsigned_shl(1);
+                match_rules_count += 1;
+                confidence += r.confidence(self.mode);
             }
         }
 
@@ -155,13 +207,14 @@ impl<T:PrimInt + Serialize> Context<T> {
             return None;
         }
 
-        let accuracy = match_rules_count as f32 / bits_count as f32;
+        let accuracy = confidence / bits_count as f32;
 
-        if accuracy == 0.0 || d_int.len() == 0 {
+        if accuracy == 0.0 || d_int.is_empty() {
             return None;
         }
 
-        let int = Information { data: d_int, name: String::from("") };
+        let mut int = Information::new(i.width, i.height, String::from(""));
+        int.data = d_int;
         Some((int, accuracy))
     }
 }
@@ -0,0 +1,314 @@
+//! Reproducible experiments: the images and the transformations are split
+//! into the train and the test parts by a seed, so the same seed gives
+//! the same parts on every run.
+
+use std::path::{Path, PathBuf};
+
+use crate::{ ContextSpace, Information, Transformation, Resampling };
+use crate::eval::EvalReport;
+use crate::sequence::{file_name, image_paths};
+use crate::error::{Error, Result};
+
+extern crate num_traits;
+use num_traits::int::PrimInt;
+
+extern crate serde;
+use serde::Serialize;
+
+extern crate rand;
+use rand::{RngCore, SeedableRng};
+
+extern crate rand_chacha;
+use rand_chacha::ChaCha8Rng;
+
+extern crate image;
+
+/// The train and the test parts of the items,
+/// both keep the items in their original order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Split<X> {
+    pub train: Vec<X>,
+    pub test: Vec<X>,
+}
+
+/// What is held out for the test in the cross validation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Holdout {
+    /// The folds of the images, all transformations are used for both parts.
+    Images,
+    /// The folds of the transformations, all images are used for both parts.
+    Transformations,
+    /// The n-th fold of the images with the n-th fold of the transformations.
+    Both,
+}
+
+/// The images of the folders as information.
+pub struct Dataset<T: PrimInt + Serialize> {
+    pub ints: Vec<Information<T>>,
+}
+
+impl<T: PrimInt + Serialize + Sync + Send> Dataset<T> {
+    /// Reads the images of the folders in the order of their paths,
+    /// other files are skipped. The information is named by the file name.
+    pub fn open<P: AsRef<Path>>(dirs: &[P]) -> Result<Dataset<T>> {
+        let mut paths: Vec<PathBuf> = Vec::new();
+        for dir in dirs {
+            paths.extend(image_paths(dir)?);
+        }
+        paths.sort();
+
+        let mut ints = Vec::with_capacity(paths.len());
+        for path in paths {
+            let name = file_name(&path);
+            ints.push(Information::from_image(&image::open(&path)?, name)?);
+        }
+        Ok(Dataset { ints })
+    }
+
+    pub fn len(&self) -> usize {
+        self.ints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ints.is_empty()
+    }
+
+    pub fn split(&self, test_ratio: f32, seed: u64) -> Split<Information<T>> {
+        split(&self.ints, test_ratio, seed)
+    }
+
+    pub fn k_fold(&self, k: usize, seed: u64) -> Result<Vec<Split<Information<T>>>> {
+        k_fold(&self.ints, k, seed)
+    }
+
+    /// Runs the experiment on every fold, see run_experiment.
+    /// The template gives the settings of the context spaces.
+    /// Returns the report of every fold, TooFewFolds if k is less than 2.
+    #[allow(clippy::too_many_arguments)]
+    pub fn cross_validate(&self, template: &ContextSpace<T>, ts: &[Transformation], k: usize, seed: u64,
+        holdout: Holdout, accuracy: f32, resampling: &Resampling) -> Result<Vec<EvalReport>> {
+        let all_ints = Split { train: self.ints.clone(), test: self.ints.clone() };
+        let all_ts = Split { train: ts.to_vec(), test: ts.to_vec() };
+
+        let int_folds = match holdout {
+            Holdout::Transformations => vec![all_ints; k],
+            _ => self.k_fold(k, seed)?,
+        };
+        let t_folds = match holdout {
+            Holdout::Images => vec![all_ts; k],
+            _ => k_fold(ts, k, seed)?,
+        };
+
+        int_folds.iter().zip(&t_folds)
+            .map(|(ints, ts)| run_experiment(template, ints, ts, accuracy, resampling))
+            .collect()
+    }
+}
+
+/// Teaches a new context space with the settings of the template
+/// every train image moved by every train transformation and
+/// evaluates it by the test images and transformations.
+pub fn run_experiment<T>(template: &ContextSpace<T>, ints: &Split<Information<T>>, ts: &Split<Transformation>,
+    accuracy: f32, resampling: &Resampling) -> Result<EvalReport>
+    where T: PrimInt + Serialize + Sync + Send {
+    let mut cs = template.empty_like();
+    for int in &ints.train {
+        for t in &ts.train {
+            cs.learn(t, &t.apply_with(int, resampling), int.clone())?;
+        }
+    }
+
+    let mut report = EvalReport::new();
+    for int in &ints.test {
+        report.add_information(&cs, int, &ts.test, accuracy, resampling);
+    }
+    Ok(report)
+}
+
+/// Puts the test_ratio part of the items chosen by the seed into the test part.
+pub fn split<X: Clone>(items: &[X], test_ratio: f32, seed: u64) -> Split<X> {
+    let test_count = ((items.len() as f32 * test_ratio).round() as usize).min(items.len());
+    let mut is_test = vec![false; items.len()];
+    for idx in shuffled_indices(items.len(), seed).into_iter().take(test_count) {
+        is_test[idx] = true;
+    }
+    split_by(items, &is_test)
+}
+
+/// Splits the items into k folds chosen by the seed, the n-th split
+/// has the n-th fold as the test part and the others as the train one.
+/// The folds differ in size by one item at most.
+/// Returns TooFewFolds if k is less than 2, since a single fold
+/// leaves nothing to train on.
+pub fn k_fold<X: Clone>(items: &[X], k: usize, seed: u64) -> Result<Vec<Split<X>>> {
+    if k < 2 {
+        return Err(Error::TooFewFolds(k));
+    }
+
+    let mut fold = vec![0; items.len()];
+    for (n, idx) in shuffled_indices(items.len(), seed).into_iter().enumerate() {
+        fold[idx] = n % k;
+    }
+
+    Ok((0..k).map(|f| {
+        let is_test: Vec<bool> = fold.iter().map(|item_fold| *item_fold == f).collect();
+        split_by(items, &is_test)
+    }).collect())
+}
+
+/// The order to learn the samples in as the pairs of the image index
+/// and the transformation index: image by image without the seed,
+/// shuffled by the seed otherwise. Either way the same arguments
+/// give the same order.
+pub fn sample_order(images: usize, transformations: usize, seed: Option<u64>) -> Vec<(usize, usize)> {
+    let pairs: Vec<(usize, usize)> = (0..images)
+        .flat_map(|i| (0..transformations).map(move |t| (i, t)))
+        .collect();
+    match seed {
+        None => pairs,
+        Some(seed) => shuffled_indices(pairs.len(), seed).into_iter().map(|idx| pairs[idx]).collect(),
+    }
+}
+
+/// Shuffles the indices with ChaCha8 keyed by the seed as it is, which gives
+/// the same numbers on every platform and version, and picks the index to swap
+/// by the remainder instead of the range sampling of rand which may change.
+fn shuffled_indices(n: usize, seed: u64) -> Vec<usize> {
+    let mut key = [0u8; 32];
+    key[..8].copy_from_slice(&seed.to_le_bytes());
+    let mut rng = ChaCha8Rng::from_seed(key);
+
+    let mut indices: Vec<usize> = (0..n).collect();
+    for i in (1..n).rev() {
+        let j = (rng.next_u64() % (i as u64 + 1)) as usize;
+        indices.swap(i, j);
+    }
+    indices
+}
+
+fn split_by<X: Clone>(items: &[X], is_test: &[bool]) -> Split<X> {
+    let mut split = Split { train: Vec::new(), test: Vec::new() };
+    for (item, test) in items.iter().zip(is_test) {
+        if *test {
+            split.test.push(item.clone());
+        } else {
+            split.train.push(item.clone());
+        }
+    }
+    split
+}
+
+#[test]
+fn can_split_by_seed() {
+    let items: Vec<u32> = (0..10).collect();
+
+    let s = split(&items, 0.3, 7);
+    assert_eq!(s.test.len(), 3);
+    assert_eq!(s.train.len(), 7);
+    assert!(s.test.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(s, split(&items, 0.3, 7));
+    assert_ne!(s, split(&items, 0.3, 8));
+
+    let folds = k_fold(&items, 3, 7).unwrap();
+    assert_eq!(folds.len(), 3);
+    let mut tested: Vec<u32> = folds.iter().flat_map(|f| f.test.clone()).collect();
+    tested.sort();
+    assert_eq!(tested, items);
+    for f in &folds {
+        assert!(f.test.len() == 3 || f.test.len() == 4);
+        assert_eq!(f.train.len() + f.test.len(), 10);
+    }
+}
+
+#[test]
+fn can_shuffle_the_same_on_every_run() {
+    // the values are fixed, a change of them breaks the saved experiments
+    assert_eq!(shuffled_indices(10, 7), vec![0, 5, 7, 4, 1, 3, 2, 8, 6, 9]);
+    assert_eq!(shuffled_indices(10, 0), vec![3, 0, 5, 9, 6, 7, 2, 4, 1, 8]);
+}
+
+#[test]
+fn can_order_samples_by_seed() {
+    assert_eq!(sample_order(2, 2, None), vec![(0, 0), (0, 1), (1, 0), (1, 1)]);
+
+    let shuffled = sample_order(3, 4, Some(5));
+    assert_eq!(shuffled, sample_order(3, 4, Some(5)));
+    assert_ne!(shuffled, sample_order(3, 4, None));
+    let mut sorted = shuffled.clone();
+    sorted.sort();
+    assert_eq!(sorted, sample_order(3, 4, None));
+}
+
+#[test]
+fn can_reject_too_few_folds() {
+    let items: Vec<u32> = (0..10).collect();
+    for k in 0..2 {
+        match k_fold(&items, k, 7) {
+            Err(Error::TooFewFolds(n)) => assert_eq!(n, k),
+            _ => panic!("{} folds are accepted", k),
+        }
+    }
+
+    let dataset = Dataset::<u16> { ints: vec![Information::new(12, 10, String::from("empty"))] };
+    let ts = [Transformation::new(0, 0, 0.0)];
+    let template = ContextSpace::<u16>::new();
+    let reports = dataset.cross_validate(&template, &ts, 1, 1, Holdout::Transformations, 0.9, &Resampling::default());
+    assert!(matches!(reports, Err(Error::TooFewFolds(1))));
+}
+
+#[test]
+fn can_cross_validate() {
+    // the lines 0 and 1 are the same, so are the lines 2 and 3
+    let mut ints = Vec::new();
+    for (n, y) in [1u32, 1, 5, 5].iter().enumerate() {
+        let mut int = Information::<u16>::new(12, 10, format!("line_{}", n));
+        for x in 2..9 {
+            int.set(x, *y, true);
+        }
+        ints.push(int);
+    }
+    let dataset = Dataset { ints };
+    let ts = [Transformation::new(0, 0, 0.0), Transformation::new(1, 0, 0.0), Transformation::new(-1, 0, 0.0)];
+    let template = ContextSpace::<u16>::new();
+    let resampling = Resampling::default();
+
+    // the seed 1 puts the lines 1 and 2 and the shifts 0 and 1 into the first test fold
+    assert_eq!(dataset.k_fold(2, 1).unwrap()[0].test.iter().map(|int| int.name.as_str()).collect::<Vec<&str>>(),
+        vec!["line_1", "line_2"]);
+    assert!(k_fold(&ts, 2, 1).unwrap()[0].test == ts[..2]);
+
+    // every held-out line has its twin learnt under the other name,
+    // so it is found with the right shift, but with the name of the twin
+    let reports = dataset.cross_validate(&template, &ts, 2, 1, Holdout::Images, 0.9, &resampling).unwrap();
+    assert_eq!(reports.len(), 2);
+    for r in &reports {
+        let metrics = r.metrics();
+        assert_eq!(metrics.samples, 2 * 3);
+        assert_eq!(metrics.not_found, 0.0);
+        assert_eq!(metrics.transformation_accuracy, 1.0);
+        assert_eq!(metrics.interpretation_accuracy, 0.0);
+    }
+
+    // every line is learnt, but the held-out shifts have no context
+    // and the learnt ones cover too few bits of the moved lines
+    let reports = dataset.cross_validate(&template, &ts, 2, 1, Holdout::Transformations, 0.9, &resampling).unwrap();
+    let samples: Vec<usize> = reports.iter().map(|r| r.metrics().samples).collect();
+    assert_eq!(samples, vec![4 * 2, 4]);
+    for r in &reports {
+        assert_eq!(r.metrics().not_found, 1.0);
+    }
+
+    let reports = dataset.cross_validate(&template, &ts, 2, 1, Holdout::Both, 0.9, &resampling).unwrap();
+    let samples: Vec<usize> = reports.iter().map(|r| r.metrics().samples).collect();
+    assert_eq!(samples, vec![2 * 2, 2]);
+    for r in &reports {
+        assert_eq!(r.metrics().not_found, 1.0);
+    }
+
+    // the control: the experiment on the learnt samples finds them all
+    let all_ints = Split { train: dataset.ints.clone(), test: dataset.ints.clone() };
+    let all_ts = Split { train: ts.to_vec(), test: ts.to_vec() };
+    let metrics = run_experiment(&template, &all_ints, &all_ts, 0.9, &resampling).unwrap().metrics();
+    assert_eq!(metrics.not_found, 0.0);
+    assert_eq!(metrics.transformation_accuracy, 1.0);
+}
@@ -0,0 +1,129 @@
+use crate::Transformation;
+
+use std::f32::consts::PI;
+
+extern crate serde;
+use serde::{Serialize, Deserialize};
+
+/// Distance between transformations as the translation with rotation,
+/// scaling and flips, every part is measured in pixels of the translation.
+/// angle_weight is the cost of one radian of the rotation, the angles are
+/// compared modulo 2π, so 0 and 2π are the same rotation.
+/// wrap, if set to the size of the information, makes the shifts measured
+/// on the torus of this size: a shift to the right edge is close to
+/// a shift to the left one.
+/// scale_weight is the cost of the scale changed e times, the scales are
+/// compared by their logarithms, so zooming in twice is as far as zooming out twice.
+/// flip_weight is the cost of every different flip.
+/// The default weights are 1, which suits only the small information,
+/// for_size weighs them by the size of the information.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DistanceMetric {
+    pub angle_weight: f32,
+    pub wrap: Option<(u32, u32)>,
+    pub scale_weight: f32,
+    pub flip_weight: f32,
+}
+
+impl Default for DistanceMetric {
+    fn default() -> Self {
+        DistanceMetric { angle_weight: 1.0, wrap: None, scale_weight: 1.0, flip_weight: 1.0 }
+    }
+}
+
+impl DistanceMetric {
+    /// The metric for the information of the given size where a radian
+    /// of rotation, the scale e times and a flip cost as much as the shift
+    /// of the border pixels they make.
+    pub fn for_size(width: u32, height: u32) -> DistanceMetric {
+        let half = width.max(height) as f32 / 2.0;
+        DistanceMetric { angle_weight: half, wrap: None, scale_weight: half, flip_weight: 2.0 * half }
+    }
+
+    /// The scales of both transformations must be positive, as
+    /// TransformationSetBuilder makes them, otherwise the distance is NaN or infinite.
+    pub fn distance(&self, from: &Transformation, to: &Transformation) -> f32 {
+        let mut dh = (to.x as i32 - from.x as i32).abs();
+        let mut dv = (to.y as i32 - from.y as i32).abs();
+
+        if let Some((width, height)) = self.wrap {
+            dh = wrap_shift(dh, width);
+            dv = wrap_shift(dv, height);
+        }
+
+        let da = angle_between(from.a, to.a) * self.angle_weight;
+        let ds = (to.scale / from.scale).ln().abs() * self.scale_weight;
+        let flips = (from.flip_h != to.flip_h) as u32 + (from.flip_v != to.flip_v) as u32;
+        let df = flips as f32 * self.flip_weight;
+
+        // the shifts may differ by up to 65535, so they are squared as floats
+        let (dh, dv) = (dh as f32, dv as f32);
+        let d = dh * dh + dv * dv + da * da + ds * ds + df * df;
+        d.sqrt()
+    }
+}
+
+/// The smallest of the shift and the shift the other way around the edge.
+fn wrap_shift(d: i32, size: u32) -> i32 {
+    if size == 0 {
+        return d;
+    }
+    let d = d % size as i32;
+    d.min(size as i32 - d)
+}
+
+/// The smallest angle between two rotations, from 0 to π.
+pub(crate) fn angle_between(a: f32, b: f32) -> f32 {
+    let da = (b - a).rem_euclid(2.0 * PI);
+    da.min(2.0 * PI - da)
+}
+
+#[test]
+fn can_measure_rotation_modulo_2pi() {
+    let m = DistanceMetric::default();
+    let t1 = Transformation::new(0, 0, 0.0);
+    let t2 = Transformation::new(0, 0, 2.0 * PI);
+    assert!(m.distance(&t1, &t2) < 1e-5);
+
+    let t2 = Transformation::new(0, 0, -0.1);
+    let t3 = Transformation::new(0, 0, 2.0 * PI - 0.1);
+    assert!((m.distance(&t1, &t2) - 0.1).abs() < 1e-5);
+    assert!(m.distance(&t2, &t3) < 1e-5);
+
+    let m = DistanceMetric::for_size(64, 32);
+    let t2 = Transformation::new(0, 0, 0.5);
+    assert!((m.distance(&t1, &t2) - 16.0).abs() < 1e-4);
+}
+
+#[test]
+fn can_measure_wrapped_shifts() {
+    let m = DistanceMetric { wrap: Some((16, 8)), ..DistanceMetric::default() };
+    let t1 = Transformation::new(-7, 3, 0.0);
+    let t2 = Transformation::new(7, -3, 0.0);
+
+    // 2 pixels around the left and right edges and 2 around the top and bottom ones
+    assert_eq!(m.distance(&t1, &t2), 8f32.sqrt());
+    assert_eq!(DistanceMetric::default().distance(&t1, &t2), (14f32 * 14.0 + 36.0).sqrt());
+}
+
+#[test]
+fn can_measure_scale_and_flips() {
+    let m = DistanceMetric::default();
+    let t = Transformation::new(0, 0, 0.0);
+    let zoom_in = Transformation { scale: 2.0, ..t.clone() };
+    let zoom_out = Transformation { scale: 0.5, ..t.clone() };
+    assert!((m.distance(&t, &zoom_in) - m.distance(&t, &zoom_out)).abs() < 1e-6);
+    assert!((m.distance(&t, &zoom_in) - 2f32.ln()).abs() < 1e-6);
+
+    let mirror = Transformation { flip_h: true, flip_v: true, ..t.clone() };
+    assert_eq!(m.distance(&t, &mirror), 2.0);
+}
+
+#[test]
+fn can_measure_the_largest_shifts() {
+    let m = DistanceMetric::default();
+    let t1 = Transformation::new(i16::MIN, i16::MIN, 0.0);
+    let t2 = Transformation::new(i16::MAX, i16::MAX, 0.0);
+    let d = m.distance(&t1, &t2);
+    assert!((d - 65535.0 * 2f32.sqrt()).abs() < 1.0);
+}
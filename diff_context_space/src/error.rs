@@ -0,0 +1,109 @@
+use std::fmt;
+use std::io;
+
+extern crate bincode;
+extern crate serde_json;
+extern crate csv;
+
+extern crate image;
+use image::ImageError;
+
+/// Errors returned by the public API of the crate.
+#[derive(Debug)]
+pub enum Error {
+    /// A file could not be read or written.
+    Io(io::Error),
+    /// Binary data could not be encoded or decoded.
+    Decode(bincode::Error),
+    /// A JSON file could not be written or parsed.
+    Json(serde_json::Error),
+    /// A CSV file could not be written or parsed.
+    Csv(csv::Error),
+    /// An image could not be opened or saved.
+    Image(ImageError),
+    /// Two pieces of information have different sizes, given as width and height.
+    SizeMismatch { expected: (u32, u32), actual: (u32, u32) },
+    /// The number of items does not match, like the transformations of a sequence.
+    CountMismatch { expected: usize, actual: usize },
+    /// A scale of the transformations is zero, negative or not a number.
+    InvalidScale(f32),
+    /// The cross validation needs at least 2 folds.
+    TooFewFolds(usize),
+    /// The image can not be represented as information.
+    UnsupportedImageDimensions { width: u32, height: u32 },
+    /// The model file was decoded, but its content is inconsistent.
+    CorruptModel(String),
+    /// The model file was saved in a newer or unknown format.
+    UnsupportedFormatVersion { version: u16, supported: u16 },
+    /// The model file is valid, but it was saved for other settings.
+    IncompatibleModel(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "i/o error: {}", e),
+            Error::Decode(e) => write!(f, "decoding error: {}", e),
+            Error::Json(e) => write!(f, "json error: {}", e),
+            Error::Csv(e) => write!(f, "csv error: {}", e),
+            Error::Image(e) => write!(f, "image error: {}", e),
+            Error::SizeMismatch { expected, actual } =>
+                write!(f, "size mismatch: expected {}x{}, actual {}x{}", expected.0, expected.1, actual.0, actual.1),
+            Error::CountMismatch { expected, actual } =>
+                write!(f, "count mismatch: expected {}, actual {}", expected, actual),
+            Error::InvalidScale(scale) => write!(f, "invalid scale {}, it must be positive", scale),
+            Error::TooFewFolds(k) => write!(f, "too few folds {}, at least 2 are needed", k),
+            Error::UnsupportedImageDimensions { width, height } =>
+                write!(f, "unsupported image dimensions {}x{}", width, height),
+            Error::CorruptModel(msg) => write!(f, "corrupt model: {}", msg),
+            Error::UnsupportedFormatVersion { version, supported } =>
+                write!(f, "unsupported format version {}, the latest supported is {}", version, supported),
+            Error::IncompatibleModel(msg) => write!(f, "incompatible model: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Decode(e) => Some(e),
+            Error::Json(e) => Some(e),
+            Error::Csv(e) => Some(e),
+            Error::Image(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(e: bincode::Error) -> Self {
+        Error::Decode(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+impl From<csv::Error> for Error {
+    fn from(e: csv::Error) -> Self {
+        Error::Csv(e)
+    }
+}
+
+impl From<ImageError> for Error {
+    fn from(e: ImageError) -> Self {
+        Error::Image(e)
+    }
+}
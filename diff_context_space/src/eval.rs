@@ -0,0 +1,248 @@
+//! Evaluation of a context space: every piece of information is moved
+//! by every transformation of a set, interpreted and compared with
+//! the expected interpretation and transformation.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+use crate::{ ContextSpace, Information, Interpretation, Transformation, Resampling };
+use crate::distance::angle_between;
+use crate::error::Result;
+
+extern crate num_traits;
+use num_traits::int::PrimInt;
+
+extern crate serde;
+use serde::Serialize;
+
+extern crate serde_json;
+extern crate csv;
+
+/// The column and the row of the confusion matrix for the samples
+/// which were not interpreted.
+pub const NOT_FOUND: &str = "<none>";
+
+/// Results of the evaluation collected sample by sample.
+#[derive(Clone, Debug, Default)]
+pub struct EvalReport {
+    samples: usize,
+    found: usize,
+    interpretation_matches: usize,
+    transformation_matches: usize,
+    dx_sum: f64,
+    dy_sum: f64,
+    da_sum: f64,
+    /// Number of the samples by the expected and the selected interpretation names.
+    confusion: BTreeMap<String, BTreeMap<String, usize>>,
+}
+
+/// The summary of the evaluation. The errors of the transformations
+/// are averaged over the interpreted samples only.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct EvalMetrics {
+    pub samples: usize,
+    /// Part of the samples interpreted as the expected interpretation.
+    pub interpretation_accuracy: f32,
+    /// Part of the samples interpreted with the expected transformation.
+    pub transformation_accuracy: f32,
+    /// Part of the samples which were interpreted at all.
+    pub coverage: f32,
+    /// Part of the samples without an interpretation.
+    pub not_found: f32,
+    pub mean_dx: f32,
+    pub mean_dy: f32,
+    /// Mean of the smallest angles between the rotations.
+    pub mean_da: f32,
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    metrics: EvalMetrics,
+    confusion: &'a BTreeMap<String, BTreeMap<String, usize>>,
+}
+
+impl EvalReport {
+    pub fn new() -> EvalReport {
+        EvalReport::default()
+    }
+
+    /// Records the result of interpreting the information with the expected
+    /// interpretation moved by the transformation t.
+    pub fn add<T: PrimInt + Serialize>(&mut self, expected: &str, t: &Transformation, found: Option<&Interpretation<T>>) {
+        self.samples += 1;
+        let selected = match found {
+            None => NOT_FOUND,
+            Some(found) => {
+                self.found += 1;
+                if found.name() == expected {
+                    self.interpretation_matches += 1;
+                }
+                if found.tran == *t {
+                    self.transformation_matches += 1;
+                }
+                self.dx_sum += (found.tran.x as i32 - t.x as i32).abs() as f64;
+                self.dy_sum += (found.tran.y as i32 - t.y as i32).abs() as f64;
+                self.da_sum += angle_between(found.tran.a, t.a) as f64;
+                found.name()
+            }
+        };
+
+        *self.confusion.entry(expected.to_string()).or_default()
+            .entry(selected.to_string()).or_default() += 1;
+    }
+
+    /// Moves the information by every transformation, interprets it
+    /// and records the results.
+    pub fn add_information<T>(&mut self, cs: &ContextSpace<T>, int: &Information<T>, ts: &[Transformation],
+        accuracy: f32, resampling: &Resampling)
+        where T: PrimInt + Serialize + Sync + Send {
+        for t in ts {
+            let i = t.apply_with(int, resampling);
+            self.add(&int.name, t, cs.interpret(&i, accuracy).as_ref());
+        }
+    }
+
+    pub fn metrics(&self) -> EvalMetrics {
+        let part = |n: usize, of: usize| if of == 0 { 0.0 } else { n as f32 / of as f32 };
+        let mean = |sum: f64| if self.found == 0 { 0.0 } else { (sum / self.found as f64) as f32 };
+
+        EvalMetrics {
+            samples: self.samples,
+            interpretation_accuracy: part(self.interpretation_matches, self.samples),
+            transformation_accuracy: part(self.transformation_matches, self.samples),
+            coverage: part(self.found, self.samples),
+            not_found: part(self.samples - self.found, self.samples),
+            mean_dx: mean(self.dx_sum),
+            mean_dy: mean(self.dy_sum),
+            mean_da: mean(self.da_sum),
+        }
+    }
+
+    /// Number of the samples with the expected interpretation
+    /// which were interpreted as the selected one.
+    pub fn confusion(&self, expected: &str, selected: &str) -> usize {
+        self.confusion.get(expected)
+            .and_then(|row| row.get(selected))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Writes the metrics and the confusion matrix as a JSON object.
+    pub fn write_json<W: Write>(&self, w: W) -> Result<()> {
+        let report = JsonReport { metrics: self.metrics(), confusion: &self.confusion };
+        serde_json::to_writer_pretty(w, &report)?;
+        Ok(())
+    }
+
+    /// Writes the metrics as a CSV file with a single row.
+    pub fn write_csv<W: Write>(&self, w: W) -> Result<()> {
+        let mut writer = csv::Writer::from_writer(w);
+        writer.serialize(self.metrics())?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the confusion matrix as a CSV file: a row for every expected
+    /// interpretation and a column for every selected one.
+    pub fn write_confusion_csv<W: Write>(&self, w: W) -> Result<()> {
+        let selected: BTreeSet<&String> = self.confusion.values().flat_map(|row| row.keys()).collect();
+
+        let mut writer = csv::Writer::from_writer(w);
+        let mut header = vec!["expected"];
+        header.extend(selected.iter().map(|s| s.as_str()));
+        writer.write_record(&header)?;
+
+        for (expected, row) in &self.confusion {
+            let mut record = vec![expected.clone()];
+            record.extend(selected.iter().map(|s| row.get(*s).copied().unwrap_or(0).to_string()));
+            writer.write_record(&record)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Evaluates the context space by every piece of information
+/// moved by every transformation.
+pub fn evaluate<T>(cs: &ContextSpace<T>, ints: &[Information<T>], ts: &[Transformation],
+    accuracy: f32, resampling: &Resampling) -> EvalReport
+    where T: PrimInt + Serialize + Sync + Send {
+    let mut report = EvalReport::new();
+    for int in ints {
+        report.add_information(cs, int, ts, accuracy, resampling);
+    }
+    report
+}
+
+#[test]
+fn can_evaluate() {
+    let mut line = Information::<u16>::new(12, 8, String::from("line"));
+    let mut dot = Information::<u16>::new(12, 8, String::from("dot"));
+    for x in 2..10 {
+        line.set(x, 4, true);
+    }
+    dot.set(5, 5, true);
+
+    let ts = [Transformation::new(0, 0, 0.0), Transformation::new(1, 0, 0.0), Transformation::new(0, 2, 0.0)];
+    let mut cs = ContextSpace::<u16>::new();
+    for t in &ts[..2] {
+        cs.learn(t, &t.apply_to(&line), line.clone()).unwrap();
+    }
+
+    let report = evaluate(&cs, &[line, dot], &ts, 0.9, &Resampling::default());
+    let metrics = report.metrics();
+
+    // the line is found for the learnt transformations only, the dot is never found
+    assert_eq!(metrics.samples, 6);
+    assert_eq!(metrics.coverage, 2.0 / 6.0);
+    assert_eq!(metrics.not_found, 4.0 / 6.0);
+    assert_eq!(metrics.interpretation_accuracy, 2.0 / 6.0);
+    assert_eq!(metrics.transformation_accuracy, 2.0 / 6.0);
+    assert_eq!(metrics.mean_dx, 0.0);
+    assert_eq!(report.confusion("line", "line"), 2);
+    assert_eq!(report.confusion("line", NOT_FOUND), 1);
+    assert_eq!(report.confusion("dot", NOT_FOUND), 3);
+
+    let mut confusion = Vec::new();
+    report.write_confusion_csv(&mut confusion).unwrap();
+    assert_eq!(String::from_utf8(confusion).unwrap(), "expected,<none>,line\ndot,3,0\nline,1,2\n");
+
+    let mut csv = Vec::new();
+    report.write_csv(&mut csv).unwrap();
+    assert!(String::from_utf8(csv).unwrap()
+        .starts_with("samples,interpretation_accuracy,transformation_accuracy,coverage,not_found,mean_dx,mean_dy,mean_da\n6,"));
+
+    let mut json = Vec::new();
+    report.write_json(&mut json).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(json["confusion"]["line"]["line"], 2);
+    assert_eq!(json["metrics"]["samples"], 6);
+}
+
+#[test]
+fn can_average_transformation_errors() {
+    let mut int = Information::<u8>::new(8, 2, String::from("dot"));
+    int.set(1, 1, true);
+    let found = Interpretation {
+        actual: int.clone(),
+        existing: int,
+        tran: Transformation::new(1, -1, 0.1),
+        context_index: 0,
+        accuracy: 1.0,
+        context_accuracy: 1.0,
+        coherence: 1.0,
+        actual_coherence: 1.0,
+    };
+
+    let mut report = EvalReport::new();
+    report.add("dot", &Transformation::new(-2, -1, 2.0 * std::f32::consts::PI - 0.1), Some(&found));
+    report.add("dot", &Transformation::new(1, 1, 0.1), Some(&found));
+    report.add("dot", &Transformation::new(0, 0, 0.0), None::<&Interpretation<u8>>);
+
+    let metrics = report.metrics();
+    assert_eq!(metrics.mean_dx, 1.5);
+    assert_eq!(metrics.mean_dy, 1.0);
+    assert!((metrics.mean_da - 0.1).abs() < 1e-5);
+    assert_eq!(metrics.interpretation_accuracy, 2.0 / 3.0);
+    assert_eq!(metrics.transformation_accuracy, 0.0);
+}
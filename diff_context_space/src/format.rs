@@ -0,0 +1,237 @@
+//! The context space file format.
+//! A file starts with the magic number, the format version and the
+//! checksum of everything after it. Then the header goes: the bit width
+//! of T, the size of the information and the training metadata.
+//! The serialized context space follows the header.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::error::{Error, Result};
+
+extern crate serde;
+use serde::{Serialize, Deserialize};
+
+pub const MAGIC: [u8; 4] = *b"DCSP";
+
+/// The version written by ContextSpace::save.
+/// Increase it on any change of the serialized context space
+/// and keep a way to read the previous versions.
+pub const FORMAT_VERSION: u16 = 1;
+
+/// Description of the context space file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FileHeader {
+    pub version: u16,
+    /// Bit width of the integer type the information is kept in.
+    pub word_bits: u32,
+    /// Size of the information, 0x0 if the context space is empty.
+    pub width: u32,
+    pub height: u32,
+    pub metadata: TrainingMetadata,
+}
+
+/// What the context space was taught with.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrainingMetadata {
+    pub samples_count: u64,
+    pub interpretations_count: u64,
+    pub contexts_count: u64,
+    pub rules_count: u64,
+}
+
+/// The part of the header which is covered by the checksum.
+#[derive(Serialize, Deserialize)]
+struct HeaderBody {
+    word_bits: u32,
+    width: u32,
+    height: u32,
+    metadata: TrainingMetadata,
+}
+
+impl fmt::Display for FileHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "format version: {}, integer width: {}, size: {}x{}",
+            self.version, self.word_bits, self.width, self.height)?;
+        write!(f, "samples: {}, interpretations: {}, contexts: {}, rules: {}",
+            self.metadata.samples_count,
+            self.metadata.interpretations_count,
+            self.metadata.contexts_count,
+            self.metadata.rules_count)
+    }
+}
+
+/// Writes the header and the payload produced by write_payload.
+/// The checksum is calculated while writing, so the file has to be
+/// seekable to put it in front of the data.
+pub fn write<W, F>(mut w: W, header: &FileHeader, write_payload: F) -> Result<()>
+    where W: Write + io::Seek, F: FnOnce(&mut HashingWriter<&mut W>) -> Result<()> {
+    w.write_all(&MAGIC)?;
+    w.write_all(&header.version.to_le_bytes())?;
+    let checksum_pos = w.stream_position()?;
+    w.write_all(&0u64.to_le_bytes())?;
+
+    let checksum = {
+        let mut hw = HashingWriter::new(&mut w);
+        let body = HeaderBody {
+            word_bits: header.word_bits,
+            width: header.width,
+            height: header.height,
+            metadata: header.metadata.clone(),
+        };
+        bincode::serialize_into(&mut hw, &body)?;
+        write_payload(&mut hw)?;
+        hw.hash()
+    };
+
+    let end_pos = w.stream_position()?;
+    w.seek(io::SeekFrom::Start(checksum_pos))?;
+    w.write_all(&checksum.to_le_bytes())?;
+    w.seek(io::SeekFrom::Start(end_pos))?;
+    w.flush()?;
+    Ok(())
+}
+
+/// Reads the magic number, the version and the header.
+/// Returns the header, the expected checksum and the reader
+/// positioned at the payload which continues the checksum calculation.
+pub fn read_header<R: Read>(mut r: R) -> Result<(FileHeader, u64, HashingReader<R>)> {
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic).map_err(not_a_model)?;
+    if magic != MAGIC {
+        return Err(Error::CorruptModel(String::from(
+            "no context space header found, files saved before the header was introduced can be converted with migrate")));
+    }
+
+    let mut version = [0u8; 2];
+    r.read_exact(&mut version).map_err(not_a_model)?;
+    let version = u16::from_le_bytes(version);
+    if version == 0 || version > FORMAT_VERSION {
+        return Err(Error::UnsupportedFormatVersion { version, supported: FORMAT_VERSION });
+    }
+
+    let mut checksum = [0u8; 8];
+    r.read_exact(&mut checksum).map_err(not_a_model)?;
+    let checksum = u64::from_le_bytes(checksum);
+
+    let mut hr = HashingReader::new(r);
+    let body: HeaderBody = bincode::deserialize_from(&mut hr)?;
+    let header = FileHeader {
+        version,
+        word_bits: body.word_bits,
+        width: body.width,
+        height: body.height,
+        metadata: body.metadata,
+    };
+
+    Ok((header, checksum, hr))
+}
+
+/// Checks that everything read matches the checksum from the file.
+pub fn verify_checksum<R: Read>(hr: HashingReader<R>, expected: u64) -> Result<()> {
+    if hr.hash() != expected {
+        return Err(Error::CorruptModel(String::from("checksum does not match")));
+    }
+    Ok(())
+}
+
+/// Checksum of the bytes, the same as the one written into the header.
+pub(crate) fn checksum(bytes: &[u8]) -> u64 {
+    let mut hash = Fnv1a::new();
+    hash.update(bytes);
+    hash.0
+}
+
+fn not_a_model(e: io::Error) -> Error {
+    if e.kind() == io::ErrorKind::UnexpectedEof {
+        return Error::CorruptModel(String::from("the file is too short to be a context space"));
+    }
+    Error::Io(e)
+}
+
+/// 64 bit FNV-1a hash.
+#[derive(Clone, Copy)]
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    fn new() -> Fnv1a {
+        Fnv1a(Fnv1a::OFFSET)
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(Fnv1a::PRIME);
+        }
+    }
+}
+
+/// Writer which hashes everything written through it.
+pub struct HashingWriter<W: Write> {
+    inner: W,
+    hash: Fnv1a,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> HashingWriter<W> {
+        HashingWriter { inner, hash: Fnv1a::new() }
+    }
+
+    pub fn hash(&self) -> u64 {
+        self.hash.0
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hash.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reader which hashes everything read through it.
+pub struct HashingReader<R: Read> {
+    inner: R,
+    hash: Fnv1a,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R) -> HashingReader<R> {
+        HashingReader { inner, hash: Fnv1a::new() }
+    }
+
+    pub fn hash(&self) -> u64 {
+        self.hash.0
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hash.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+#[test]
+fn can_hash_while_reading_and_writing() {
+    let data = b"context space";
+
+    let mut hw = HashingWriter::new(Vec::new());
+    hw.write_all(data).unwrap();
+
+    let mut hr = HashingReader::new(&data[..]);
+    let mut buf = Vec::new();
+    hr.read_to_end(&mut buf).unwrap();
+
+    assert_eq!(hw.hash(), hr.hash());
+    assert_ne!(hw.hash(), Fnv1a::OFFSET);
+}
@@ -3,25 +3,176 @@ use num_traits::int::PrimInt;
 
 use std::fmt;
 
+use crate::error::{Error, Result};
+
 extern crate serde;
 use serde::{Serialize, Deserialize};
 
 extern crate image;
 use image::{DynamicImage, GrayImage, Luma};
 
+/// Default value of a grey pixel above which it is parsed as 1.
+pub(crate) const THRESHOLD:u8 = 50;
 
+/// Information is a bit matrix of width x height bits.
+/// Every row is kept in one or several elements of T, so the
+/// size of the matrix is not tied to the bit width of T.
+/// A row is a number where the right pixel is the lowest bit:
+/// the first element of the row keeps the right most pixels,
+/// the last one keeps the left most pixels and unused high bits.
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Information<T:PrimInt + Serialize> {
     pub data: Vec<T>,
+    pub width: u32,
+    pub height: u32,
     pub name: String
 }
 
 impl<T:PrimInt + Serialize> Information<T> {
-    pub fn coherence_to(&self, to: &Information<T>) -> Result<f32, &str> {
-        if self.data.len() != to.data.len() {
-            return Err("Lengths do not match");
+    /// Creates an empty information of the given size.
+    pub fn new(width: u32, height: u32, name: String) -> Information<T> {
+        let len = Information::<T>::words_per_row_for(width) * height as usize;
+        let data = vec!(T::zero(); len);
+        Information { data, width, height, name }
+    }
+
+    /// Creates an information where every element of data is a row,
+    /// so the width is the bit width of T.
+    pub fn from_rows(data: Vec<T>, name: String) -> Information<T> {
+        let width = T::zero().count_zeros();
+        let height = data.len() as u32;
+        Information { data, width, height, name }
+    }
+
+    /// Bit width of T.
+    pub fn word_bits() -> u32 {
+        T::zero().count_zeros()
+    }
+
+    /// Number of elements of T required to keep a row of the given width.
+    pub fn words_per_row_for(width: u32) -> usize {
+        let bits = Information::<T>::word_bits();
+        width.div_ceil(bits) as usize
+    }
+
+    pub fn words_per_row(&self) -> usize {
+        Information::<T>::words_per_row_for(self.width)
+    }
+
+    /// Returns the elements keeping the row y.
+    pub fn row(&self, y: u32) -> &[T] {
+        let wpr = self.words_per_row();
+        let start = y as usize * wpr;
+        &self.data[start..start + wpr]
+    }
+
+    /// Index of the element and the mask of the bit keeping the pixel xy.
+    /// Panics if the pixel is outside of the information.
+    fn locate(&self, x: u32, y: u32) -> (usize, T) {
+        self.assert_inside(x, y);
+        // X coordinate of a pixel starts from left,
+        // but data starts from the right.
+        // That is why bit position is calculated as (w - 1 - x).
+        let bits = Information::<T>::word_bits();
+        let b = self.width - 1 - x;
+        let idx = y as usize * self.words_per_row() + (b / bits) as usize;
+        let mask = T::one().unsigned_shl(b % bits);
+        (idx, mask)
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> bool {
+        let (idx, mask) = self.locate(x, y);
+        self.data[idx] & mask != T::zero()
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, value: bool) {
+        let (idx, mask) = self.locate(x, y);
+        if value {
+            self.data[idx] = self.data[idx] | mask;
+        } else {
+            self.data[idx] = self.data[idx] & !mask;
         }
-        
+    }
+
+    /// Returns offsets of all set bits. The offset of a bit is
+    /// its index in data multiplied by the bit width of T plus its
+    /// position inside the element.
+    pub fn ones(&self) -> impl Iterator<Item = u32> + '_ {
+        let bits = Information::<T>::word_bits();
+        self.data.iter()
+            .enumerate()
+            .filter(|(_, d)| **d != T::zero())
+            .flat_map(move |(idx, d)| {
+                let d = *d;
+                (0..bits)
+                    .filter(move |b| d & T::one().unsigned_shl(*b) != T::zero())
+                    .map(move |b| idx as u32 * bits + b)
+            })
+    }
+
+    /// Sets the bit with the given offset, see ones().
+    pub fn set_offset(&mut self, offset: u32) {
+        let bits = Information::<T>::word_bits();
+        let idx = (offset / bits) as usize;
+        self.data[idx] = self.data[idx] | T::one().unsigned_shl(offset % bits);
+    }
+
+    /// Checks if the bit with the given offset is set, see ones().
+    pub fn get_offset(&self, offset: u32) -> bool {
+        let bits = Information::<T>::word_bits();
+        let idx = (offset / bits) as usize;
+        idx < self.data.len() && self.data[idx] & T::one().unsigned_shl(offset % bits) != T::zero()
+    }
+
+    /// Offset of the bit of the pixel, see ones().
+    /// Panics if the pixel is outside of the information.
+    pub fn offset_of(&self, x: u32, y: u32) -> u32 {
+        self.assert_inside(x, y);
+        let bits = Information::<T>::word_bits();
+        y * self.words_per_row() as u32 * bits + (self.width - 1 - x)
+    }
+
+    /// Without the check a too large x underflows the bit position
+    /// and the pixel of another row is taken.
+    fn assert_inside(&self, x: u32, y: u32) {
+        assert!(x < self.width && y < self.height, "pixel {}x{} is outside of {}x{}", x, y, self.width, self.height);
+    }
+
+    /// Returns the information shifted by dx pixels to the right and dy pixels down.
+    /// The pixels coming from outside repeat the nearest edge pixel
+    /// the same way as imageproc translate does.
+    pub fn shifted(&self, dx: i32, dy: i32) -> Information<T> {
+        let mut out = Information::new(self.width, self.height, self.name.clone());
+        if self.width == 0 || self.height == 0 {
+            return out;
+        }
+
+        let h = self.height as i32;
+        let words_per_row = self.words_per_row();
+        for y in 0..h {
+            let y_in = (y - dy).clamp(0, h - 1);
+            let from = y as usize * words_per_row;
+            shift_row(self.row(y_in as u32), &mut out.data[from..from + words_per_row], self.width, dx);
+        }
+        out
+    }
+
+    /// Checks that the other information has the same size.
+    pub fn same_size(&self, other: &Information<T>) -> bool {
+        self.width == other.width && self.height == other.height
+    }
+
+    /// Returns SizeMismatch if the other information has another size.
+    pub fn check_same_size(&self, other: &Information<T>) -> Result<()> {
+        if !self.same_size(other) || self.data.len() != other.data.len() {
+            return Err(Error::SizeMismatch { expected: (self.width, self.height), actual: (other.width, other.height) });
+        }
+        Ok(())
+    }
+
+    pub fn coherence_to(&self, to: &Information<T>) -> Result<f32> {
+        self.check_same_size(to)?;
+
         let mut to_ones_count = 0;
         let mut conj_ones_count = 0;
         for i in 0 .. self.data.len() {
@@ -43,103 +194,137 @@ impl<T:PrimInt + Serialize> Information<T> {
         Ok(coherence)
     }
 
+    /// Part of the bits set in both pieces of information among the bits
+    /// set in any of them, 1 for the same ones and 0 if they do not overlap.
+    pub fn similarity_to(&self, to: &Information<T>) -> Result<f32> {
+        self.check_same_size(to)?;
+
+        let mut both = 0;
+        let mut any = 0;
+        for (a, b) in self.data.iter().zip(&to.data) {
+            both += (*a & *b).count_ones();
+            any += (*a | *b).count_ones();
+        }
+
+        if any == 0 {
+            return Ok(0.);
+        }
+        Ok(both as f32 / any as f32)
+    }
+
     pub fn get_empty_elt(&self) -> T {
         T::zero()
     }
 
     /// Creates an information from a grey image.
     /// White pixels are transformed as 1, black as 0.
-    pub fn from_image(dyn_img: &DynamicImage, name: String) -> Information<T>
+    pub fn from_image(dyn_img: &DynamicImage, name: String) -> Result<Information<T>>
         where T: PrimInt {
-        const THRESHOLD:u8 = 50;
-        let i = Information::<T>::from_image_threshold(dyn_img, THRESHOLD, name);
-        i
+        Information::<T>::from_image_threshold(dyn_img, THRESHOLD, name)
     }
 
     /// This function creates an Information structure from every pixel
     /// of a grey image. The value of a pixel higher than the given threshold 
     /// is parsed as 1, lower as 0.
-    pub fn from_image_threshold(dyn_img: &DynamicImage, threshold: u8, name: String) -> Information<T> {
+    /// Returns an error if the image is empty.
+    pub fn from_image_threshold(dyn_img: &DynamicImage, threshold: u8, name: String) -> Result<Information<T>> {
         let img = dyn_img.to_luma8(); // Grey image
-        let h = img.height();
-        let mut data = Vec::<T>::with_capacity(h as usize);
-        let w = img.width();
-
-        for row in 0..h {
-            let mut mask = T::one();
-            let mut d = T::zero();
-
-            for col in 0..w {
-                // X coordinate of a pixel starts from left,
-                // but data starts from the right.
-                // As a result, a column value is opposite between them.
-                // That is why x pixel position is calculated as (w - 1 - col).
-                let p = img.get_pixel(w - 1 - col, row);
-                let p_val = p.0[0];
-                if p_val > threshold {
-                    d = d | mask;
-                }
-                mask = mask.unsigned_shl(1);
-            }
+        if img.width() == 0 || img.height() == 0 {
+            return Err(Error::UnsupportedImageDimensions { width: img.width(), height: img.height() });
+        }
 
-            data.push(d);
+        Ok(Information::from_luma_threshold(&img, threshold, name))
+    }
+
+    /// Parses a grey image which is known not to be empty.
+    pub(crate) fn from_luma_threshold(img: &GrayImage, threshold: u8, name: String) -> Information<T> {
+        let mut info = Information::new(img.width(), img.height(), name);
+
+        for (x, y, p) in img.enumerate_pixels() {
+            if p.0[0] > threshold {
+                info.set(x, y, true);
+            }
         }
 
-        Information { data, name }
+        info
     }
 
     pub fn to_image(&self) -> DynamicImage {
-        let zero = T::zero();
-        let w = zero.count_zeros();
-        let h = self.data.len() as u32;
-        let mut img = GrayImage::new(w, h);
-        for row in 0..h {
-            let mut mask = T::one();
-            for col in 0..w {
-                let pixel = if (self.data[row as usize] & mask) > zero {
+        let mut img = GrayImage::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let pixel = if self.get(x, y) {
                     Luma([255])
                 } else {
                     Luma([0])
                 };
 
-                // X coordinate of a pixel starts from left,
-                // but data starts from the right.
-                // As a result, a column value is opposite between them.
-                // That is why x pixel position is calculated as (w - 1 - col).
-                img.put_pixel(w - 1 - col, row, pixel);
-
-                mask = mask.unsigned_shl(1);
+                img.put_pixel(x, y, pixel);
             }
         }
         DynamicImage::ImageLuma8(img)
     }
 }
 
+/// Shifts the row of the given width by dx pixels to the right (to the lower bits)
+/// repeating the edge pixel in the place of the shifted out ones.
+fn shift_row<T: PrimInt>(src: &[T], dst: &mut [T], width: u32, dx: i32) {
+    let bits = T::zero().count_zeros();
+    let bit = |b: u32| src[(b / bits) as usize] & T::one().unsigned_shl(b % bits) != T::zero();
+    let shift = dx.unsigned_abs().min(width);
+    let (word_shift, bit_shift) = ((shift / bits) as usize, shift % bits);
+
+    for (i, d) in dst.iter_mut().enumerate() {
+        *d = if dx >= 0 {
+            let lo = src.get(i + word_shift).copied().unwrap_or_else(T::zero);
+            let hi = src.get(i + word_shift + 1).copied().unwrap_or_else(T::zero);
+            let carry = if bit_shift > 0 { hi.unsigned_shl(bits - bit_shift) } else { T::zero() };
+            lo.unsigned_shr(bit_shift) | carry
+        } else {
+            let hi = i.checked_sub(word_shift).map(|j| src[j]).unwrap_or_else(T::zero);
+            let lo = i.checked_sub(word_shift + 1).map(|j| src[j]).unwrap_or_else(T::zero);
+            let carry = if bit_shift > 0 { lo.unsigned_shr(bits - bit_shift) } else { T::zero() };
+            hi.unsigned_shl(bit_shift) | carry
+        };
+    }
+
+    // the bits above the width are not used
+    let top_bits = width - (dst.len() as u32 - 1) * bits;
+    if top_bits < bits {
+        let last = dst.len() - 1;
+        dst[last] = dst[last] & !(!T::zero()).unsigned_shl(top_bits);
+    }
+
+    // the left edge pixel is the highest bit, the right one is the lowest
+    let (edge, from) = if dx >= 0 { (bit(width - 1), width - shift) } else { (bit(0), 0) };
+    if edge {
+        for b in from..from + shift {
+            let idx = (b / bits) as usize;
+            dst[idx] = dst[idx] | T::one().unsigned_shl(b % bits);
+        }
+    }
+}
+
 impl<T: PrimInt + Serialize> fmt::Display for Information<T> where T: fmt::Binary + PrimInt {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for d in &self.data {
-            let res = writeln!(f, "{:#010b}", d);
-            match res {
-                Ok(_) => (),
-                Err(e) => return Err(e),
-            };
+        for y in 0..self.height {
+            // the highest element of the row goes first
+            for d in self.row(y).iter().rev() {
+                write!(f, "{:#010b} ", d)?;
+            }
+            writeln!(f)?;
         }
-        return Ok(());
+        Ok(())
     }
 }
 
 impl<T: PrimInt + Serialize> std::cmp::PartialEq for Information<T> where T: PrimInt {
     fn eq(&self, other: &Self) -> bool {
-        if self.name != other.name {
+        if self.name != other.name || !self.same_size(other) {
             return false;
         }
 
-        for i in 0..self.data.len() {
-            if self.data[i] != other.data[i] {
-                return false;
-            }
-        }
-        return true;
+        self.data == other.data
     }
 }
 
@@ -147,42 +332,53 @@ impl<T: PrimInt + Serialize> std::cmp::PartialEq for Information<T> where T: Pri
 fn can_calculate_coherence_to() {
     let d = vec![0x01, 0x01, 0x01, 0x01, 0x01];
     let n = String::from("name1");
-    let i1 = Information { data: d, name: n};
+    let i1 = Information::from_rows(d, n);
 
     let d = vec![0x01, 0x02, 0x01, 0x01, 0x01];
     let n = String::from("name2");
-    let i2 = Information { data: d, name: n};
+    let i2 = Information::from_rows(d, n);
 
     let c = i1.coherence_to(&i2);
-    assert_eq!(c, Ok(0.8));
+    assert_eq!(c.unwrap(), 0.8);
 }
 
 #[test]
 fn can_calculate_full_coherence_to() {
     let d = vec![0x01, 0x01, 0x01, 0x01, 0x01];
     let n = String::from("name1");
-    let i1 = Information { data: d, name: n };
+    let i1 = Information::from_rows(d, n);
 
     let d = vec![0x01, 0x01, 0x01, 0x01, 0x01];
     let n = String::from("name2");
-    let i2 = Information { data: d, name: n };
+    let i2 = Information::from_rows(d, n);
 
     let c = i1.coherence_to(&i2);
-    assert_eq!(c, Ok(1.0));
+    assert_eq!(c.unwrap(), 1.0);
 }
 
 #[test]
 fn can_get_error_on_coherence_to_calculation() {
     let d = vec![0x01, 0x01, 0x01, 0x01, 0x01];
     let n = String::from("name1");
-    let i1 = Information { data: d, name: n };
+    let i1 = Information::from_rows(d, n);
 
     let d = vec![0x01, 0x02, 0x01, 0x01];
     let n = String::from("name2");
-    let i2 = Information { data: d, name: n };
+    let i2 = Information::from_rows(d, n);
 
     let e = i1.coherence_to(&i2);
-    assert_eq!(e, Err("Lengths do not match"));
+    assert!(matches!(e, Err(Error::SizeMismatch { expected: (32, 5), actual: (32, 4) })));
+}
+
+#[test]
+fn can_get_error_on_similarity_of_other_width() {
+    // both take four words, but the pixels are placed differently
+    let wide = Information::<u8>::new(16, 2, String::from("wide"));
+    let tall = Information::<u8>::new(8, 4, String::from("tall"));
+    assert_eq!(wide.data.len(), tall.data.len());
+
+    let e = wide.similarity_to(&tall);
+    assert!(matches!(e, Err(Error::SizeMismatch { expected: (16, 2), actual: (8, 4) })));
 }
 
 #[test]
@@ -193,7 +389,7 @@ fn can_build_from_image() {
     let name = String::from("smile_bw_16x16.png");
     let img = image::open(path).unwrap();
     
-    let actual = Information::<u16>::from_image(&img, name);
+    let actual = Information::<u16>::from_image(&img, name.clone()).unwrap();
 
     // The data pieces are shown in a reflected to the right
     // way because a number here has lower index in the right
@@ -217,7 +413,7 @@ fn can_build_from_image() {
         0b_1000000000000001,    // 14
         0b_0111111111111110,    // 15
     );
-    let expected = Information { data, name };
+    let expected = Information::from_rows(data, name);
 
     assert_eq!(actual.data[0], expected.data[0]);
     assert_eq!(actual.data[1], expected.data[1]);
@@ -237,6 +433,69 @@ fn can_build_from_image() {
     assert_eq!(actual.data[15], expected.data[15]);
 }
 
+#[test]
+fn can_build_from_image_wider_than_t() {
+    let mut path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("files/tests/information/smile_bw_32x32.png");
+    let img = image::open(path).unwrap();
+    let name = String::from("smile_bw_32x32.png");
+
+    let actual = Information::<u8>::from_image(&img, name.clone()).unwrap();
+    let expected = Information::<u32>::from_image(&img, name).unwrap();
+
+    assert_eq!(actual.width, 32);
+    assert_eq!(actual.height, 32);
+    assert_eq!(actual.words_per_row(), 4);
+    for y in 0..32 {
+        for x in 0..32 {
+            assert_eq!(actual.get(x, y), expected.get(x, y));
+        }
+    }
+    assert_eq!(actual.to_image(), expected.to_image());
+}
+
+#[test]
+fn can_build_from_non_square_image() {
+    let mut img = GrayImage::new(20, 3);
+    img.put_pixel(0, 0, Luma([255]));
+    img.put_pixel(19, 2, Luma([255]));
+    let img = DynamicImage::ImageLuma8(img);
+
+    let actual = Information::<u16>::from_image(&img, String::from("20x3")).unwrap();
+
+    assert_eq!(actual.data, vec!(
+        0b_0000_0000_0000_0000, 0b_1000, // 0, left pixel is the highest bit
+        0b_0000_0000_0000_0000, 0b_0000, // 1
+        0b_0000_0000_0000_0001, 0b_0000, // 2, right pixel is the lowest bit
+    ));
+    assert_eq!(actual.to_image(), img);
+}
+
+#[test]
+fn can_get_error_on_empty_image() {
+    let img = DynamicImage::new_luma8(0, 16);
+
+    let e = Information::<u16>::from_image(&img, String::from("empty"));
+    assert!(matches!(e, Err(Error::UnsupportedImageDimensions { width: 0, height: 16 })));
+}
+
+#[test]
+fn can_iterate_ones() {
+    let mut i = Information::<u8>::new(12, 2, String::new());
+    i.set(11, 0, true);
+    i.set(3, 0, true);
+    i.set(0, 1, true);
+
+    let ones: Vec<u32> = i.ones().collect();
+    assert_eq!(ones, vec![0, 8, 27]);
+
+    let mut actual = Information::<u8>::new(12, 2, String::new());
+    for offset in ones {
+        actual.set_offset(offset);
+    }
+    assert!(actual == i);
+}
+
 #[test]
 fn can_to_image() {
     let data = vec!(
@@ -258,10 +517,10 @@ fn can_to_image() {
         0b_0111111111111110,    // 15
     );
     let name = String::from("name1");
-    let expected = Information::<u16> { data, name };
+    let expected = Information::<u16>::from_rows(data, name.clone());
 
     let img = expected.to_image();
-    let actual = Information::<u16>::from_image(&img, name);
+    let actual = Information::<u16>::from_image(&img, name.clone()).unwrap();
 
     assert_eq!(actual.data[0], expected.data[0]);
     assert_eq!(actual.data[1], expected.data[1]);
@@ -288,7 +547,7 @@ fn can_read_same_image() {
     let expected_img = image::open(expected_path).unwrap();
     let name = String::from("smile_bw_16x16.png");
 
-    let expected = Information::<u16>::from_image(&expected_img, name);
+    let expected = Information::<u16>::from_image(&expected_img, name.clone()).unwrap();
     let img = expected.to_image();
     
     let mut path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -296,7 +555,7 @@ fn can_read_same_image() {
     img.save_with_format(&path, image::ImageFormat::Png).unwrap();
 
     let actual_img = image::open(&path).unwrap();
-    let actual = Information::<u16>::from_image(&actual_img, name);
+    let actual = Information::<u16>::from_image(&actual_img, name.clone()).unwrap();
     
     assert_eq!(actual.data[0], expected.data[0]);
     assert_eq!(actual.data[1], expected.data[1]);
@@ -323,7 +582,7 @@ fn can_equal() {
     let img = image::open(path).unwrap();
     let name = String::from("smile_bw_16x16.png");
 
-    let i1 = Information::<u16>::from_image(&img, name);
+    let i1 = Information::<u16>::from_image(&img, name.clone()).unwrap();
 
     // The data pieces are shown in a reflected to the right
     // way because a number here has lower index in the right
@@ -347,11 +606,11 @@ fn can_equal() {
         0b_1000000000000001,    // 14
         0b_0111111111111110,    // 15
     );
-    let i2 = Information { data, name };
+    let i2 = Information::from_rows(data, name);
 
     let actual = i1 == i2;
 
-    assert_eq!(actual, true);
+    assert!(actual);
 }
 
 #[test]
@@ -361,7 +620,7 @@ fn can_not_equal() {
     let img = image::open(path).unwrap();
     let name = String::from("smile_bw_16x16.png");
 
-    let i1 = Information::<u16>::from_image(&img, name);
+    let i1 = Information::<u16>::from_image(&img, name.clone()).unwrap();
 
     // The data pieces are shown in a reflected to the right
     // way because a number here has lower index in the right
@@ -385,9 +644,30 @@ fn can_not_equal() {
         0b_1000000000000001,    // 14
         0b_0111111111111110,    // 15
     );
-    let i2 = Information { data, name };
+    let i2 = Information::from_rows(data, name);
 
     let actual = i1 != i2;
 
-    assert_eq!(actual, true);
-}
\ No newline at end of file
+    assert!(actual);
+}
+
+#[test]
+#[should_panic(expected = "pixel 16x0 is outside of 16x2")]
+fn can_not_get_pixel_outside() {
+    let i = Information::<u8>::new(16, 2, String::new());
+    i.get(16, 0);
+}
+
+#[test]
+#[should_panic(expected = "pixel 0x2 is outside of 16x2")]
+fn can_not_set_pixel_outside() {
+    let mut i = Information::<u8>::new(16, 2, String::new());
+    i.set(0, 2, true);
+}
+
+#[test]
+#[should_panic(expected = "pixel 17x1 is outside of 16x2")]
+fn can_not_get_offset_outside() {
+    let i = Information::<u8>::new(16, 2, String::new());
+    i.offset_of(17, 1);
+}
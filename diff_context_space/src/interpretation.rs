@@ -0,0 +1,78 @@
+use std::fmt;
+
+use crate::{ Information, Transformation };
+
+extern crate num_traits;
+use num_traits::int::PrimInt;
+
+extern crate serde;
+use serde::{Serialize, Serializer};
+
+/// An interpretation of the information found by a context.
+/// Serialized without the information bits: the existing interpretation
+/// is written by its name and the output of the context is skipped.
+#[derive(Clone, Serialize)]
+pub struct Interpretation<T: PrimInt + Serialize> {
+    /// The existing interpretation the output of the context is coherent to.
+    #[serde(rename = "interpretation", serialize_with = "serialize_name")]
+    pub existing: Information<T>,
+    /// Transformation of the context.
+    pub tran: Transformation,
+    pub context_index: usize,
+    /// The product of the context accuracy and the coherence.
+    pub accuracy: f32,
+    /// Accuracy of the rules of the context.
+    pub context_accuracy: f32,
+    /// Part of the bits of the existing interpretation the output has.
+    pub coherence: f32,
+    /// Part of the bits of the output the existing interpretation has.
+    pub actual_coherence: f32,
+    /// The output of the context.
+    #[serde(skip)]
+    pub actual: Information<T>,
+}
+
+impl<T: PrimInt + Serialize> Interpretation<T> {
+    /// The name of the existing interpretation.
+    pub fn name(&self) -> &str {
+        &self.existing.name
+    }
+}
+
+fn serialize_name<T, S>(int: &Information<T>, s: S) -> std::result::Result<S::Ok, S::Error>
+    where T: PrimInt + Serialize, S: Serializer {
+    s.serialize_str(&int.name)
+}
+
+impl<T: PrimInt + Serialize> fmt::Display for Interpretation<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "int: {}, acc: {}, context acc: {}, coh: {}, actual coh: {}, context: {}, t: {}",
+            self.existing.name,
+            self.accuracy,
+            self.context_accuracy,
+            self.coherence,
+            self.actual_coherence,
+            self.context_index,
+            self.tran)
+    }
+}
+
+#[test]
+fn can_log_interpretation() {
+    let mut existing = Information::<u8>::new(8, 2, String::from("dot"));
+    existing.set(1, 1, true);
+    let found = Interpretation {
+        actual: existing.clone(),
+        existing,
+        tran: Transformation::new(1, -1, 0.0),
+        context_index: 3,
+        accuracy: 0.5,
+        context_accuracy: 0.5,
+        coherence: 1.0,
+        actual_coherence: 1.0,
+    };
+
+    assert_eq!(found.to_string(), "int: dot, acc: 0.5, context acc: 0.5, coh: 1, actual coh: 1, context: 3, t: x: 1 y: -1 a: 0");
+    assert_eq!(serde_json::to_string(&found).unwrap(),
+        r#"{"interpretation":"dot","tran":{"x":1,"y":-1,"a":0.0,"scale":1.0,"flip_h":false,"flip_v":false},"context_index":3,"accuracy":0.5,"context_accuracy":0.5,"coherence":1.0,"actual_coherence":1.0}"#);
+}
@@ -0,0 +1,115 @@
+//! The previous context space formats kept only to read the old files.
+//! V0 is the format used before information got its own width and height
+//! and the rules got their statistics, it had no file header.
+
+use crate::{ Transformation, Information, Context, ContextSpace };
+use crate::rule::{Rule, RuleMode};
+
+extern crate num_traits;
+use num_traits::int::PrimInt;
+
+extern crate serde;
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct InformationV0<T> {
+    pub data: Vec<T>,
+    pub name: String
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct TransformationV0 {
+    pub x: i16,
+    pub y: i16,
+    pub a: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct RuleV0<T> {
+    pub i: InformationV0<T>,
+    pub int: InformationV0<T>
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ContextV0<T> {
+    pub tran: TransformationV0,
+    pub rules: Vec<RuleV0<T>>
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ContextSpaceV0<T> {
+    pub interpretations: Vec<InformationV0<T>>,
+    pub contexts: Vec<ContextV0<T>>
+}
+
+impl<T: PrimInt + Serialize> InformationV0<T> {
+    /// Every element of the old information was a row.
+    fn upgrade(self) -> Information<T> {
+        Information::from_rows(self.data, self.name)
+    }
+}
+
+impl TransformationV0 {
+    pub fn upgrade(self) -> Transformation {
+        Transformation::new(self.x, self.y, self.a)
+    }
+}
+
+impl<T: PrimInt + Sync + Send + Serialize> ContextSpaceV0<T> {
+    /// Converts the old context space into the current one.
+    /// The old rules were learnt by intersection and did not count
+    /// the samples, so every rule is considered to be learnt once.
+    pub fn upgrade(self) -> ContextSpace<T> {
+        let mut cs = ContextSpace::<T>::new();
+
+        cs.interpretations = self.interpretations.into_iter()
+            .map(|int| int.upgrade())
+            .collect();
+
+        cs.contexts = self.contexts.into_iter()
+            .map(|c| {
+                // every old rule information has exactly one bit set
+                let rules = c.rules.into_iter()
+                    .filter_map(|r| {
+                        let src = r.i.upgrade().ones().next()?;
+                        Some(Rule::new(src, &r.int.upgrade()))
+                    })
+                    .collect();
+                Context::with_rules(c.tran.upgrade(), RuleMode::Intersection, rules)
+            })
+            .collect();
+
+        cs
+    }
+}
+
+#[test]
+fn can_upgrade_context_space() {
+    let old = ContextSpaceV0::<u8> {
+        interpretations: vec![InformationV0 { data: vec![0b_0000_0100, 0], name: String::from("dot") }],
+        contexts: vec![ContextV0 {
+            tran: TransformationV0 { x: 1, y: 0, a: 0.0 },
+            rules: vec![RuleV0 {
+                i: InformationV0 { data: vec![0b_0000_0010, 0], name: String::new() },
+                int: InformationV0 { data: vec![0b_0000_0100, 0], name: String::new() },
+            }],
+        }],
+    };
+
+    let path = std::env::temp_dir().join(format!("diff_context_space_cs_legacy_8x2_{}.bin", std::process::id()));
+    let f = std::fs::File::create(&path).unwrap();
+    bincode::serialize_into(f, &old).unwrap();
+
+    let cs = ContextSpace::<u8>::load_legacy(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let int = &cs.interpretations[0];
+    assert_eq!((int.width, int.height), (8, 2));
+    assert!(int.get(5, 0));
+
+    let mut i = Information::<u8>::new(8, 2, String::new());
+    i.set(6, 0, true);
+    let (actual, accuracy) = cs.contexts[0].interpret(&i).unwrap();
+    assert_eq!(actual.data, int.data);
+    assert_eq!(accuracy, 1.0);
+}
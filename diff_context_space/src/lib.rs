@@ -1,12 +1,38 @@
+mod error;
 mod information;
 mod transformation;
 mod transformations;
+mod distance;
 mod context;
 mod context_space;
+mod interpretation;
 mod rule;
+mod consolidation;
+mod legacy;
+mod memory_usage;
+mod format;
+mod mapped;
+mod checkpoint;
+mod sequence;
+mod eval;
+mod dataset;
 
+pub use error::{Error, Result};
 pub use information::Information;
-pub use transformation::Transformation;
-pub use transformations::build_and_save_transformations;
+pub use transformation::{Transformation, Interpolation, Resampling, ANGLE_RESOLUTION, SCALE_RESOLUTION};
+pub use distance::DistanceMetric;
+pub use transformations::{build_and_save_transformations, load_transformations, save_transformations, TransformationSetBuilder};
+pub use transformations::{read_transformations, write_transformations, TransformationFormat};
+pub use transformations::infer_transformation;
 pub use context::Context;
-pub use context_space::ContextSpace;
\ No newline at end of file
+pub use rule::{Rule, RuleMode, Consolidated};
+pub use context_space::{ContextSpace, LearningMode, SAME_TRANSFORMATION_DISTANCE};
+pub use interpretation::Interpretation;
+pub use memory_usage::MemoryUsage;
+pub use mapped::MappedContextSpace;
+pub use checkpoint::Checkpoint;
+pub use sequence::{Sequence, SequenceTransformations, FrameTrace, image_paths};
+pub use eval::{evaluate, EvalReport, EvalMetrics, NOT_FOUND};
+pub use dataset::{Dataset, Split, Holdout, split, k_fold, run_experiment, sample_order};
+pub use format::{FileHeader, TrainingMetadata, FORMAT_VERSION};
+pub use consolidation::{ConsolidationPolicy, ConsolidationSchedule};
\ No newline at end of file
@@ -0,0 +1,503 @@
+//! Read-only context space which is memory mapped from the file
+//! instead of being deserialized. Only the rules of the bits set in the
+//! interpreted information are touched, so opening the model is instant
+//! and the operating system pages in only the parts which are used.
+//!
+//! All numbers are little-endian. The file consists of:
+//! - the header: magic, version, bit width of T, width, height,
+//!   number of interpretations, contexts, rules (u32 each) and bits (u64);
+//! - contexts: x, y (i32), a, scale (f32), flips (u32: 1 horizontal, 2 vertical),
+//!   first rule, rules count (u32);
+//! - rules sorted by src within the context: src (u32), confidence (f32),
+//!   first bit (u64), bits count (u32);
+//! - bits: the interpretation offsets of all rules (u32);
+//! - interpretations: name offset (u64) and name length (u32);
+//! - interpretations data: words of T, word_bits / 8 bytes each;
+//! - names of the interpretations in UTF-8.
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::marker::PhantomData;
+use std::path::Path;
+
+use crate::{ Transformation, Information, Interpretation, Context, ContextSpace, Rule };
+use crate::error::{Error, Result};
+
+extern crate memmap2;
+use memmap2::Mmap;
+
+extern crate rayon;
+use rayon::prelude::*;
+
+extern crate num_traits;
+use num_traits::int::PrimInt;
+
+extern crate serde;
+use serde::Serialize;
+
+extern crate ordered_float;
+use ordered_float::OrderedFloat;
+
+const MAGIC: [u8; 4] = *b"DCSM";
+const VERSION: u32 = 1;
+
+const HEADER_SIZE: usize = 40;
+const CONTEXT_SIZE: usize = 28;
+const RULE_SIZE: usize = 20;
+const BIT_SIZE: usize = 4;
+const INTERPRETATION_SIZE: usize = 12;
+
+/// Read-only context space working directly on the mapped file.
+pub struct MappedContextSpace<T> {
+    mmap: Mmap,
+    pub width: u32,
+    pub height: u32,
+    interpretations_count: usize,
+    contexts_count: usize,
+    rules_pos: usize,
+    bits_pos: usize,
+    bits_count: usize,
+    interpretations_pos: usize,
+    data_pos: usize,
+    names_pos: usize,
+    word: PhantomData<T>,
+}
+
+/// Writes the context space in the mapped format.
+pub(crate) fn write<T: PrimInt + Sync + Send + Serialize>(cs: &ContextSpace<T>, path: &Path) -> Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+
+    let (width, height) = cs.interpretations.first()
+        .map(|int| (int.width, int.height))
+        .unwrap_or((0, 0));
+    let rules_count: usize = cs.contexts.iter().map(|c| c.rules.len()).sum();
+    let bits_count: u64 = cs.contexts.iter()
+        .flat_map(|c| c.rules.iter())
+        .map(|r| r.int.len() as u64)
+        .sum();
+
+    w.write_all(&MAGIC)?;
+    for v in &[VERSION, Information::<T>::word_bits(), width, height,
+        cs.interpretations.len() as u32, cs.contexts.len() as u32, rules_count as u32] {
+        w.write_all(&v.to_le_bytes())?;
+    }
+    w.write_all(&bits_count.to_le_bytes())?;
+
+    let mut first_rule = 0u32;
+    for c in &cs.contexts {
+        w.write_all(&(c.tran.x as i32).to_le_bytes())?;
+        w.write_all(&(c.tran.y as i32).to_le_bytes())?;
+        w.write_all(&c.tran.a.to_le_bytes())?;
+        w.write_all(&c.tran.scale.to_le_bytes())?;
+        let flips = c.tran.flip_h as u32 | (c.tran.flip_v as u32) << 1;
+        w.write_all(&flips.to_le_bytes())?;
+        w.write_all(&first_rule.to_le_bytes())?;
+        w.write_all(&(c.rules.len() as u32).to_le_bytes())?;
+        first_rule += c.rules.len() as u32;
+    }
+
+    let mut first_bit = 0u64;
+    for c in &cs.contexts {
+        for r in sorted_rules(c) {
+            w.write_all(&r.src.to_le_bytes())?;
+            w.write_all(&r.confidence(c.mode).to_le_bytes())?;
+            w.write_all(&first_bit.to_le_bytes())?;
+            w.write_all(&(r.int.len() as u32).to_le_bytes())?;
+            first_bit += r.int.len() as u64;
+        }
+    }
+    for c in &cs.contexts {
+        for r in sorted_rules(c) {
+            for offset in &r.int {
+                w.write_all(&offset.to_le_bytes())?;
+            }
+        }
+    }
+
+    let mut name_offset = 0u64;
+    for int in &cs.interpretations {
+        w.write_all(&name_offset.to_le_bytes())?;
+        w.write_all(&(int.name.len() as u32).to_le_bytes())?;
+        name_offset += int.name.len() as u64;
+    }
+
+    for int in &cs.interpretations {
+        cs.interpretations[0].check_same_size(int)?;
+        for word in &int.data {
+            w.write_all(&word_to_bytes(*word))?;
+        }
+    }
+
+    for int in &cs.interpretations {
+        w.write_all(int.name.as_bytes())?;
+    }
+
+    w.flush()?;
+    Ok(())
+}
+
+impl<T: PrimInt + Sync + Send + Serialize> MappedContextSpace<T> {
+    /// Maps the file written by ContextSpace::save_mapped.
+    /// The file must not be changed while it is mapped.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MappedContextSpace<T>> {
+        let f = File::open(path)?;
+        // the mapping is read-only and the file is not expected to be modified
+        let mmap = unsafe { Mmap::map(&f)? };
+
+        if mmap.len() < HEADER_SIZE || mmap[..4] != MAGIC {
+            return Err(Error::CorruptModel(String::from("no mapped context space header found")));
+        }
+
+        let u32_at = |pos: usize| read_u32(&mmap, pos);
+        let version = u32_at(4);
+        if version != VERSION {
+            return Err(Error::UnsupportedFormatVersion { version: version as u16, supported: VERSION as u16 });
+        }
+
+        let word_bits = Information::<T>::word_bits();
+        if u32_at(8) != word_bits {
+            let msg = format!("saved with {} bit integers, loaded as {} bit", u32_at(8), word_bits);
+            return Err(Error::IncompatibleModel(msg));
+        }
+
+        let width = u32_at(12);
+        let height = u32_at(16);
+        let interpretations_count = u32_at(20) as usize;
+        let contexts_count = u32_at(24) as usize;
+        let rules_count = u32_at(28) as usize;
+
+        // the counts come from the file, so a damaged one may give any sizes
+        let too_large = || Error::CorruptModel(String::from("the sizes in the header are too large"));
+        let bits_count = usize::try_from(read_u64(&mmap, 32)).map_err(|_| too_large())?;
+        let after = |pos: usize, count: usize, size: usize| count.checked_mul(size)
+            .and_then(|bytes| bytes.checked_add(pos))
+            .ok_or_else(too_large);
+
+        let rules_pos = after(HEADER_SIZE, contexts_count, CONTEXT_SIZE)?;
+        let bits_pos = after(rules_pos, rules_count, RULE_SIZE)?;
+        let interpretations_pos = after(bits_pos, bits_count, BIT_SIZE)?;
+        let data_pos = after(interpretations_pos, interpretations_count, INTERPRETATION_SIZE)?;
+        let info_bytes = (height as usize).checked_mul(Information::<T>::words_per_row_for(width))
+            .and_then(|words| words.checked_mul(word_bits as usize / 8))
+            .ok_or_else(too_large)?;
+        let names_pos = after(data_pos, interpretations_count, info_bytes)?;
+
+        if mmap.len() < names_pos {
+            return Err(Error::CorruptModel(format!("the file is {} bytes, expected at least {}", mmap.len(), names_pos)));
+        }
+
+        Ok(MappedContextSpace {
+            mmap, width, height,
+            interpretations_count, contexts_count,
+            rules_pos, bits_pos, bits_count,
+            interpretations_pos, data_pos, names_pos,
+            word: PhantomData,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.contexts_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contexts_count == 0
+    }
+
+    pub fn interpretations_len(&self) -> usize {
+        self.interpretations_count
+    }
+
+    /// Transformation of the context.
+    pub fn transformation(&self, ctx: usize) -> Transformation {
+        let pos = HEADER_SIZE + ctx * CONTEXT_SIZE;
+        let flips = read_u32(&self.mmap, pos + 16);
+        Transformation {
+            x: read_u32(&self.mmap, pos) as i32 as i16,
+            y: read_u32(&self.mmap, pos + 4) as i32 as i16,
+            a: f32::from_bits(read_u32(&self.mmap, pos + 8)),
+            scale: f32::from_bits(read_u32(&self.mmap, pos + 12)),
+            flip_h: flips & 1 != 0,
+            flip_v: flips & 2 != 0,
+        }
+    }
+
+    /// Reads the interpretation with the given index from the file.
+    pub fn interpretation(&self, idx: usize) -> Information<T> {
+        let pos = self.interpretations_pos + idx * INTERPRETATION_SIZE;
+        let name_offset = read_u64(&self.mmap, pos) as usize;
+        let name_len = read_u32(&self.mmap, pos + 8) as usize;
+        let name = self.names_pos.checked_add(name_offset)
+            .and_then(|from| Some(from..from.checked_add(name_len)?))
+            .and_then(|range| self.mmap.get(range))
+            .map(|bytes| String::from_utf8_lossy(bytes).into_owned())
+            .unwrap_or_default();
+
+        let mut int = Information::new(self.width, self.height, name);
+        let word_bytes = Information::<T>::word_bits() as usize / 8;
+        let start = self.data_pos + idx * int.data.len() * word_bytes;
+        for (i, d) in int.data.iter_mut().enumerate() {
+            *d = word_from_bytes(&self.mmap[start + i * word_bytes..start + (i + 1) * word_bytes]);
+        }
+        int
+    }
+
+    /// Finds the rule of the context by the bit offset of the information.
+    /// Returns the confidence and the range of the interpretation bits.
+    fn rule_for(&self, ctx: usize, offset: u32) -> Option<(f32, usize, usize)> {
+        let pos = HEADER_SIZE + ctx * CONTEXT_SIZE;
+        let first = read_u32(&self.mmap, pos + 20) as usize;
+        let count = read_u32(&self.mmap, pos + 24) as usize;
+
+        // the rules of the context have to be within the rules of the file
+        let last_pos = first.checked_add(count)?.checked_mul(RULE_SIZE)?.checked_add(self.rules_pos)?;
+        if last_pos > self.bits_pos {
+            return None;
+        }
+
+        let rule_pos = |r: usize| self.rules_pos + (first + r) * RULE_SIZE;
+        let (mut lo, mut hi) = (0, count);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            let pos = rule_pos(mid);
+            let src = read_u32(&self.mmap, pos);
+            if src == offset {
+                let confidence = f32::from_bits(read_u32(&self.mmap, pos + 4));
+                let first_bit = read_u64(&self.mmap, pos + 8) as usize;
+                let bits = read_u32(&self.mmap, pos + 16) as usize;
+                if first_bit.checked_add(bits)? > self.bits_count {
+                    return None;
+                }
+                return Some((confidence, first_bit, first_bit + bits));
+            }
+            if src < offset {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        None
+    }
+
+    /// The same as Context::interpret, but for the mapped context.
+    fn interpret_context(&self, ctx: usize, i: &Information<T>) -> Option<(Information<T>, f32)> {
+        let bits = Information::<T>::word_bits();
+        let mut d_int = vec!(T::zero(); i.data.len());
+
+        let mut bits_count = 0;
+        let mut match_rules_count = 0;
+        let mut confidence = 0.0;
+
+        for offset in i.ones() {
+            bits_count += 1;
+
+            if let Some((rule_confidence, from, to)) = self.rule_for(ctx, offset) {
+                for b in from..to {
+                    let offset = read_u32(&self.mmap, self.bits_pos + b * BIT_SIZE);
+                    if let Some(d) = d_int.get_mut((offset / bits) as usize) {
+                        *d = *d | T::one().unsigned_shl(offset % bits);
+                    }
+                }
+
+                match_rules_count += 1;
+                confidence += rule_confidence;
+            }
+        }
+
+        if bits_count == 0 || match_rules_count == 0 {
+            return None;
+        }
+
+        let accuracy = confidence / bits_count as f32;
+        if accuracy == 0.0 || d_int.is_empty() {
+            return None;
+        }
+
+        let mut int = Information::new(i.width, i.height, String::from(""));
+        int.data = d_int;
+        Some((int, accuracy))
+    }
+
+    /// The same as ContextSpace::interpret.
+    pub fn interpret(&self, i: &Information<T>, accuracy: f32) -> Option<Interpretation<T>> {
+        if self.interpretations_count > 0 && (i.width, i.height) != (self.width, self.height) {
+            return None;
+        }
+
+        let candidates: Vec<(usize, Information<T>, f32)> = (0..self.contexts_count)
+            .into_par_iter()
+            .filter_map(|ctx| {
+                let (actual_int, actual_int_accuracy) = self.interpret_context(ctx, i)?;
+                if actual_int_accuracy >= accuracy {
+                    return Some((ctx, actual_int, actual_int_accuracy));
+                }
+                None
+            })
+            .collect();
+
+        if candidates.is_empty() {
+            return None;
+        }
+
+        // every interpretation is read once and compared with all candidates,
+        // a candidate keeps the most coherent interpretation and the best
+        // candidate is the first one with the highest accuracy
+        let coherences: Vec<Option<(usize, OrderedFloat<f32>)>> = (0..self.interpretations_count)
+            .into_par_iter()
+            .map(|idx| {
+                let existing_int = self.interpretation(idx);
+                candidates.iter()
+                    .map(|(_, actual_int, _)| {
+                        let coherence = actual_int.coherence_to(&existing_int).ok()?;
+                        if coherence >= accuracy { Some((idx, OrderedFloat(coherence))) } else { None }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .reduce(|| vec![None; candidates.len()], |a, b| {
+                a.into_iter().zip(b).map(|(a, b)| match (a, b) {
+                    (Some(a), Some(b)) => Some(if b.1 > a.1 || (b.1 == a.1 && b.0 < a.0) { b } else { a }),
+                    (a, b) => a.or(b),
+                }).collect()
+            });
+
+        let (best, (idx, coherence)) = coherences.into_iter()
+            .enumerate()
+            .filter_map(|(n, c)| Some((n, c?)))
+            .max_by(|(an, a), (bn, b)| {
+                let a_acc = OrderedFloat(candidates[*an].2 * a.1.into_inner());
+                let b_acc = OrderedFloat(candidates[*bn].2 * b.1.into_inner());
+                a_acc.cmp(&b_acc).then(bn.cmp(an))
+            })?;
+
+        let (ctx, actual, context_accuracy) = candidates.into_iter().nth(best)?;
+        let existing = self.interpretation(idx);
+        let actual_coherence = existing.coherence_to(&actual).ok()?;
+        let coherence = coherence.into_inner();
+        Some(Interpretation {
+            existing,
+            tran: self.transformation(ctx),
+            context_index: ctx,
+            accuracy: context_accuracy * coherence,
+            context_accuracy,
+            coherence,
+            actual_coherence,
+            actual,
+        })
+    }
+}
+
+/// The rules are looked up by binary search, so they go sorted by src.
+fn sorted_rules<T: PrimInt + Serialize>(c: &Context<T>) -> Vec<&Rule<T>> {
+    let mut rules: Vec<_> = c.rules.iter().collect();
+    rules.sort_by_key(|r| r.src);
+    rules
+}
+
+/// The bytes of the word from the lowest one. The bits are copied
+/// as they are, so the negative words of the signed types are kept too.
+fn word_to_bytes<T: PrimInt>(word: T) -> Vec<u8> {
+    let word_bytes = T::zero().count_zeros() as usize / 8;
+    (0..word_bytes)
+        .map(|k| (0..8).fold(0u8, |byte, bit| {
+            let set = word & T::one().unsigned_shl(8 * k as u32 + bit) != T::zero();
+            if set { byte | 1 << bit } else { byte }
+        }))
+        .collect()
+}
+
+fn word_from_bytes<T: PrimInt>(bytes: &[u8]) -> T {
+    bytes.iter().enumerate()
+        .flat_map(|(k, byte)| (0..8).filter(move |bit| byte & 1 << bit != 0).map(move |bit| 8 * k as u32 + bit))
+        .fold(T::zero(), |word, bit| word | T::one().unsigned_shl(bit))
+}
+
+fn read_u32(bytes: &[u8], pos: usize) -> u32 {
+    let mut b = [0u8; 4];
+    b.copy_from_slice(&bytes[pos..pos + 4]);
+    u32::from_le_bytes(b)
+}
+
+fn read_u64(bytes: &[u8], pos: usize) -> u64 {
+    let mut b = [0u8; 8];
+    b.copy_from_slice(&bytes[pos..pos + 8]);
+    u64::from_le_bytes(b)
+}
+
+#[test]
+fn can_interpret_mapped() {
+    let mut int = Information::<u16>::new(20, 6, String::from("line"));
+    for x in 3..11 {
+        int.set(x, 2, true);
+    }
+    let mut other = Information::<u16>::new(20, 6, String::from("column"));
+    for y in 0..5 {
+        other.set(14, y, true);
+    }
+
+    let ts = [Transformation::new(1, 1, 0.0), Transformation::new(-2, 0, 0.0)];
+    let mut cs = ContextSpace::<u16>::new();
+    for t in &ts {
+        cs.learn(t, &t.apply_to(&int), int.clone()).unwrap();
+        cs.learn(t, &t.apply_to(&other), other.clone()).unwrap();
+    }
+
+    let path = std::env::temp_dir().join(format!("diff_context_space_cs_20x6_mapped_{}.bin", std::process::id()));
+    cs.save_mapped(&path).unwrap();
+
+    let mapped = MappedContextSpace::<u16>::open(&path).unwrap();
+    assert_eq!(mapped.len(), cs.len());
+    assert!(mapped.interpretation(1) == other);
+
+    let i = ts[1].apply_to(&other);
+    let actual = mapped.interpret(&i, 0.9).unwrap();
+    let expected = cs.interpret(&i, 0.9).unwrap();
+    assert!(actual.existing == expected.existing);
+    assert!(actual.tran == expected.tran);
+    assert_eq!(actual.context_index, expected.context_index);
+    assert_eq!(actual.accuracy, expected.accuracy);
+    assert_eq!(actual.context_accuracy, expected.context_accuracy);
+    assert_eq!(actual.coherence, expected.coherence);
+    assert_eq!(actual.actual_coherence, expected.actual_coherence);
+
+    let e = MappedContextSpace::<u8>::open(&path);
+    assert!(matches!(e, Err(Error::IncompatibleModel(_))));
+    drop(mapped);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn can_map_signed_words() {
+    let mut int = Information::<i8>::new(8, 3, String::from("corner"));
+    int.set(0, 0, true);    // the highest bit makes the word negative
+    int.set(7, 1, true);
+    let t = Transformation::new(0, 0, 0.0);
+    let mut cs = ContextSpace::<i8>::new();
+    cs.learn(&t, &int, int.clone()).unwrap();
+    assert!(int.data[0] < 0);
+
+    let dir = std::env::temp_dir().join(format!("diff_context_space_mapped_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("cs_8x3_i8_mapped.bin");
+    cs.save_mapped(&path).unwrap();
+
+    let mapped = MappedContextSpace::<i8>::open(&path).unwrap();
+    assert!(mapped.interpretation(0) == int);
+    assert_eq!(mapped.interpret(&int, 0.9).unwrap().name(), "corner");
+
+    // a damaged header with huge counts
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes[32..40].copy_from_slice(&u64::MAX.to_le_bytes());
+    std::fs::write(&path, &bytes).unwrap();
+    let e = MappedContextSpace::<i8>::open(&path);
+    assert!(matches!(e, Err(Error::CorruptModel(_))));
+
+    // a truncated file
+    let mut bytes = std::fs::read(dir.join("cs_8x3_i8_mapped.bin")).unwrap();
+    bytes[32..40].copy_from_slice(&0u64.to_le_bytes());
+    bytes.truncate(HEADER_SIZE + 4);
+    std::fs::write(&path, &bytes).unwrap();
+    let e = MappedContextSpace::<i8>::open(&path);
+    assert!(matches!(e, Err(Error::CorruptModel(_))));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,29 @@
+use std::fmt;
+
+/// Approximate memory usage of a context space.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryUsage {
+    pub interpretations: usize,
+    pub interpretations_bytes: usize,
+    pub contexts: usize,
+    pub contexts_bytes: usize,
+    pub rules: usize,
+    /// Number of the interpretation bits kept by all rules.
+    pub rule_bits: usize,
+    pub rules_bytes: usize,
+}
+
+impl MemoryUsage {
+    pub fn total_bytes(&self) -> usize {
+        self.interpretations_bytes + self.contexts_bytes + self.rules_bytes
+    }
+}
+
+impl fmt::Display for MemoryUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "interpretations: {}, {} bytes", self.interpretations, self.interpretations_bytes)?;
+        writeln!(f, "contexts: {}, {} bytes", self.contexts, self.contexts_bytes)?;
+        writeln!(f, "rules: {}, interpretation bits: {}, {} bytes", self.rules, self.rule_bits, self.rules_bytes)?;
+        write!(f, "total: {} bytes", self.total_bytes())
+    }
+}
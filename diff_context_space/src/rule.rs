@@ -1,71 +1,277 @@
 extern crate num_traits;
 use num_traits::int::PrimInt;
 
+use std::marker::PhantomData;
+use std::mem;
+
 use crate::Information;
+use crate::consolidation::ConsolidationPolicy;
 
 extern crate serde;
-use serde::{Serialize, Deserialize};
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
 
 extern crate image;
 use image::{DynamicImage, GrayImage, Luma};
 
 
-/// A rule keeps information i 
-/// and its interpretation int
+/// The way a rule builds its interpretation from the learnt samples.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum RuleMode {
+    /// Only the bits set in every learnt sample stay in the interpretation.
+    #[default]
+    Intersection,
+    /// The bits set in at least the threshold part of the learnt
+    /// samples stay in the interpretation.
+    Counting { threshold: f32 },
+}
+
+impl RuleMode {
+    /// Whether the rules need the hits to build the interpretation.
+    pub fn counts_hits(&self) -> bool {
+        matches!(self, RuleMode::Counting { .. })
+    }
+}
+
+/// What the consolidation did to a rule.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Consolidated {
+    /// The rule is the same as before.
+    Unchanged,
+    /// The support, the hits or the interpretation of the rule changed.
+    Changed,
+    /// The rule does not keep any bit anymore and should be removed.
+    Emptied,
+}
+
+/// A rule keeps the bit of information i it is learnt for (src)
+/// and the bits of its interpretation int. Both are kept as bit offsets
+/// (see Information::ones()), the interpretation offsets are sorted.
+/// Besides, it keeps the temporary memory of the learnt samples:
+/// the number of the samples (support) and how many times
+/// every bit of the interpretation was set (hits). The hits are
+/// sorted by the bit offset as well. They are needed only by the
+/// Counting mode and the consolidation, without them the rule keeps
+/// no more than its interpretation. The hits are saved as an empty
+/// list if they are not kept.
 #[derive(Serialize, Deserialize)]
 pub struct Rule<T:PrimInt + Serialize> {
-    pub i: Information<T>,
-    pub int: Information<T>
+    pub src: u32,
+    pub int: Vec<u32>,
+    pub support: f32,
+    #[serde(serialize_with = "serialize_hits", deserialize_with = "deserialize_hits")]
+    pub hits: Option<Vec<(u32, f32)>>,
+    #[serde(skip)]
+    word: PhantomData<T>
 }
 
 impl<T:PrimInt + Serialize> Rule<T> {
-    pub fn new(i: &Information<T>, int: &Information<T>) -> Rule<T> {
-        let i_clone = i.clone();
-        let int_clone = int.clone();
-        Rule { i: i_clone, int: int_clone }
+    pub fn new(src: u32, int: &Information<T>) -> Rule<T> {
+        Rule::with_weight(src, int, 1.0)
+    }
+
+    /// The rule learnt from a sample which counts as the weight part of a sample.
+    pub fn with_weight(src: u32, int: &Information<T>, weight: f32) -> Rule<T> {
+        Rule::learnt(src, int, weight, true)
+    }
+
+    /// The same as with_weight, but the hits are kept only if keep_hits is set.
+    pub fn learnt(src: u32, int: &Information<T>, weight: f32, keep_hits: bool) -> Rule<T> {
+        let int: Vec<u32> = int.ones().collect();
+        let hits = if keep_hits {
+            Some(int.iter().map(|offset| (*offset, weight)).collect())
+        } else {
+            None
+        };
+        Rule { src, int, support: weight, hits, word: PhantomData }
+    }
+
+    /// Adds one more interpretation to the rule. Every bit of int
+    /// is counted, the interpretation of the rule is updated
+    /// according to the mode.
+    pub fn learn(&mut self, int: &Information<T>, mode: RuleMode) {
+        self.learn_weighted(int, mode, 1.0);
+    }
+
+    /// The same as learn, but the sample counts as the weight part of a sample.
+    /// The intersection can not take a part of a sample, so the samples
+    /// with the weight below 1 only add to the statistics
+    /// used on the consolidation.
+    pub fn learn_weighted(&mut self, int: &Information<T>, mode: RuleMode, weight: f32) {
+        self.learn_with_hits(int, mode, weight, true);
+    }
+
+    /// The same as learn_weighted, but in the Intersection mode the hits
+    /// are counted only if keep_hits is set. If the rule has not kept
+    /// the hits so far, they start from its interpretation.
+    pub fn learn_with_hits(&mut self, int: &Information<T>, mode: RuleMode, weight: f32, keep_hits: bool) {
+        if keep_hits || mode.counts_hits() {
+            let support = self.support;
+            let own_int = &self.int;
+            let hits = self.hits.get_or_insert_with(|| own_int.iter().map(|offset| (*offset, support)).collect());
+            for offset in int.ones() {
+                match hits.binary_search_by_key(&offset, |h| h.0) {
+                    Ok(idx) => hits[idx].1 += weight,
+                    Err(idx) => hits.insert(idx, (offset, weight)),
+                }
+            }
+        }
+        self.support += weight;
+
+        match mode {
+            RuleMode::Intersection => {
+                if weight >= 1.0 {
+                    self.int.retain(|offset| int.get_offset(*offset));
+                }
+            },
+            RuleMode::Counting { threshold } => {
+                self.rebuild_int(threshold * self.support);
+            }
+        }
+    }
+
+    /// Probability of the interpretation bits: the mean part of the
+    /// samples where they were set. The intersection keeps only
+    /// the bits seen in every sample, so it is sure of them.
+    /// A rule learnt from less than a whole sample, like the one learnt
+    /// only from the neighbour samples, is as sure as the weight it has got.
+    pub fn confidence(&self, mode: RuleMode) -> f32 {
+        let learnt = self.support.min(1.0);
+        match mode {
+            RuleMode::Intersection => learnt,
+            RuleMode::Counting { threshold } => {
+                let min_hits = threshold * self.support;
+                let (count, sum) = self.hits().iter()
+                    .filter(|h| h.1 >= min_hits)
+                    .fold((0, 0.0), |(count, sum), h| (count + 1, sum + h.1));
+
+                if count == 0 || self.support == 0.0 {
+                    return 0.0;
+                }
+                sum / (count as f32 * self.support) * learnt
+            }
+        }
+    }
+
+    /// The hits of the bits, empty if they are not kept.
+    pub fn hits(&self) -> &[(u32, f32)] {
+        self.hits.as_deref().unwrap_or(&[])
+    }
+
+    /// Sets the interpretation to the bits hit at least min_hits times.
+    /// Returns true if the interpretation changed.
+    fn rebuild_int(&mut self, min_hits: f32) -> bool {
+        let int: Vec<u32> = self.hits().iter()
+            .filter(|h| h.1 >= min_hits)
+            .map(|h| h.0)
+            .collect();
+        if int == self.int {
+            return false;
+        }
+        self.int = int;
+        true
+    }
+
+    /// Rebuilds the interpretation from the learnt statistics
+    /// removing the bits that seem not to take part in the transformation.
+    /// The rules without the hits have nothing to consolidate.
+    pub fn consolidate(&mut self, policy: &ConsolidationPolicy, mode: RuleMode) -> Consolidated {
+        let hits = match self.hits.as_mut() {
+            None if self.int.is_empty() => return Consolidated::Emptied,
+            None => return Consolidated::Unchanged,
+            Some(hits) => hits,
+        };
+
+        let mut changed = false;
+        if let Some(window) = policy.decay_window {
+            if self.support > window {
+                let scale = window / self.support;
+                self.support = window;
+                hits.iter_mut().for_each(|h| h.1 *= scale);
+                changed = true;
+            }
+        }
+
+        if self.support < policy.min_support {
+            return if changed { Consolidated::Changed } else { Consolidated::Unchanged };
+        }
+
+        let min_hits = policy.min_hit_ratio * self.support;
+        let len = hits.len();
+        hits.retain(|h| h.1 >= min_hits);
+        changed |= hits.len() != len;
+
+        changed |= match mode {
+            RuleMode::Intersection => self.rebuild_int(0.0),
+            RuleMode::Counting { threshold } => self.rebuild_int(threshold * self.support),
+        };
+
+        if self.hits().is_empty() {
+            Consolidated::Emptied
+        } else if changed {
+            Consolidated::Changed
+        } else {
+            Consolidated::Unchanged
+        }
     }
 
+    /// Approximate number of bytes the rule takes in memory.
+    pub fn memory_size(&self) -> usize {
+        mem::size_of::<Rule<T>>()
+            + self.int.capacity() * mem::size_of::<u32>()
+            + self.hits.as_ref().map_or(0, |hits| hits.capacity()) * mem::size_of::<(u32, f32)>()
+    }
 
     /// Prints out the rules into an image
     /// where left part is interpretation, right part is information. 
     /// This means that the image size is twice wider 
-    /// than the width of i or int.
+    /// than the given width of i or int.
     /// White pixels show active ones.
-    pub fn to_image(&self) -> DynamicImage {
-        let zero = T::zero();
-        let w = zero.count_zeros();
+    pub fn to_image(&self, width: u32, height: u32) -> DynamicImage {
+        let mut i = Information::<T>::new(width, height, String::new());
+        i.set_offset(self.src);
+
+        let mut int = Information::<T>::new(width, height, String::new());
+        for offset in &self.int {
+            int.set_offset(*offset);
+        }
+
+        let w = width;
         let full_w = 2 * w;
-        let h = self.i.data.len() as usize;
-        let mut img = GrayImage::new(full_w, h as u32);
+        let h = height;
+        let mut img = GrayImage::new(full_w, h);
         for row in 0..h {
-            let mut mask = T::one();
             for col in 0..full_w {
-                if col == w {
-                    // it is the time for i, reset the mask
-                    mask = T::one();
-                }
-
-                let data_row = if col < w {
-                    self.int.data[row]
+                // the columns go from the lowest bit of a row to the highest one
+                let (info, bit) = if col < w {
+                    (&int, col)
                 } else {
-                    self.i.data[row]
+                    (&i, col - w)
                 };
-                
-                let pixel = if (data_row & mask) > zero {
+
+                let pixel = if info.get(w - 1 - bit, row) {
                     Luma([255])
                 } else {
                     Luma([0])
                 };
 
-                img.put_pixel(col, row as u32, pixel);
-
-                mask = mask.unsigned_shl(1);
+                img.put_pixel(col, row, pixel);
             }
         }
         DynamicImage::ImageLuma8(img)
     }
 }
 
+fn serialize_hits<S: Serializer>(hits: &Option<Vec<(u32, f32)>>, s: S) -> std::result::Result<S::Ok, S::Error> {
+    hits.as_deref().unwrap_or(&[]).serialize(s)
+}
+
+/// An empty list is read as the hits which are not kept: a rule which has
+/// never been hit keeps no bits, so its hits start from nothing anyway.
+fn deserialize_hits<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<Option<Vec<(u32, f32)>>, D::Error> {
+    let hits = Vec::<(u32, f32)>::deserialize(d)?;
+    Ok(if hits.is_empty() { None } else { Some(hits) })
+}
+
 #[test]
 fn can_to_image() {
     let i_data = vec!(
@@ -86,7 +292,7 @@ fn can_to_image() {
         0b_0000000000000000,    // 14
         0b_0000000000000000,    // 15
     );
-    let i = Information::<u16> { data: i_data };
+    let i = Information::<u16>::from_rows(i_data, String::new());
 
     let int_data = vec!(
         0b_1000000000000000,    // 0, initial position
@@ -107,15 +313,93 @@ fn can_to_image() {
         0b_0000000000000000,    // 15
     );
 
-    let int = Information::<u16> { data: int_data };
+    let int = Information::<u16>::from_rows(int_data, String::new());
 
-    let r = Rule::new(&i, &int);
+    let r = Rule::new(i.ones().next().unwrap(), &int);
 
-    let actual = r.to_image();
+    let actual = r.to_image(16, 16);
 
     let mut path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     path.push("files/tests/rule/can_to_image.png");
     let expected = image::open(path).unwrap();
 
     assert_eq!(actual, expected);
+}
+
+#[test]
+fn can_consolidate_after_unlucky_sample() {
+    let mut i = Information::<u8>::new(8, 2, String::new());
+    i.set(0, 0, true);
+
+    let mut int = Information::<u8>::new(8, 2, String::new());
+    int.set(1, 0, true);
+    int.set(5, 1, true);   // noise
+
+    let mut r = Rule::new(i.ones().next().unwrap(), &int);
+
+    let mut clipped = Information::<u8>::new(8, 2, String::new());
+    r.learn(&clipped, RuleMode::Intersection);      // the bit is missing because of the clipping
+    assert!(r.int.is_empty());
+
+    clipped.set(1, 0, true);
+    r.learn(&clipped, RuleMode::Intersection);
+    r.learn(&clipped, RuleMode::Intersection);
+
+    let policy = ConsolidationPolicy { min_support: 3.0, min_hit_ratio: 0.7, decay_window: None };
+    assert_eq!(r.consolidate(&policy, RuleMode::Intersection), Consolidated::Changed);
+
+    let mut expected = Information::<u8>::new(8, 2, String::new());
+    expected.set(1, 0, true);
+    assert_eq!(r.int, expected.ones().collect::<Vec<u32>>());
+    assert_eq!(r.hits().len(), 1);
+
+    // nothing is left to remove
+    assert_eq!(r.consolidate(&policy, RuleMode::Intersection), Consolidated::Unchanged);
+}
+
+#[test]
+fn can_decay_old_samples() {
+    let mut i = Information::<u8>::new(8, 1, String::new());
+    i.set(0, 0, true);
+
+    let mut int = Information::<u8>::new(8, 1, String::new());
+    int.set(7, 0, true);
+
+    let mut r = Rule::new(i.ones().next().unwrap(), &int);
+    for _ in 0..9 {
+        r.learn(&int, RuleMode::Intersection);
+    }
+
+    let policy = ConsolidationPolicy { min_support: 1.0, min_hit_ratio: 0.5, decay_window: Some(4.0) };
+    assert_eq!(r.consolidate(&policy, RuleMode::Intersection), Consolidated::Changed);
+    assert_eq!(r.support, 4.0);
+    assert_eq!(r.hits(), &[(0, 4.0)]);
+
+    // the rule forgets the bit if it is not seen anymore
+    let empty = Information::<u8>::new(8, 1, String::new());
+    for _ in 0..8 {
+        r.learn(&empty, RuleMode::Intersection);
+    }
+    assert_eq!(r.consolidate(&policy, RuleMode::Intersection), Consolidated::Emptied);
+}
+
+#[test]
+fn can_learn_by_counting() {
+    let mut i = Information::<u8>::new(8, 1, String::new());
+    i.set(0, 0, true);
+
+    let mut int = Information::<u8>::new(8, 1, String::new());
+    int.set(1, 0, true);
+    let empty = Information::<u8>::new(8, 1, String::new());
+
+    let mode = RuleMode::Counting { threshold: 0.6 };
+    let mut r = Rule::new(i.ones().next().unwrap(), &int);
+    r.learn(&int, mode);
+    r.learn(&empty, mode);     // unlucky sample does not erase the bit
+    assert_eq!(r.int, int.ones().collect::<Vec<u32>>());
+    assert_eq!(r.confidence(mode), 2.0 / 3.0);
+
+    r.learn(&empty, mode);     // 2 of 4 is lower than the threshold
+    assert!(r.int.is_empty());
+    assert_eq!(r.confidence(mode), 0.0);
 }
\ No newline at end of file
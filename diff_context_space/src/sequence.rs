@@ -0,0 +1,313 @@
+//! Learning from the sequences of frames, like a video of a moving object.
+//! Every pair of the consecutive frames is a sample: the next frame is
+//! the information and the previous one is its interpretation. The
+//! transformations between the frames are read from a sidecar file
+//! or inferred, see ContextSpace::learn_unlabeled.
+
+use std::cmp::Ordering;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use crate::{ Information, Interpretation, Transformation, ContextSpace };
+use crate::transformations::load_transformations;
+use crate::error::{Error, Result};
+
+extern crate num_traits;
+use num_traits::int::PrimInt;
+
+extern crate serde;
+use serde::Serialize;
+
+extern crate image;
+use image::{AnimationDecoder, DynamicImage, ImageFormat};
+use image::gif::GifDecoder;
+
+/// Names of the sidecar files looked for in a folder of frames.
+const SIDECAR_NAMES: [&str; 3] = ["transformations.json", "transformations.csv", "transformations.bin"];
+
+/// Frames of a sequence in their order.
+pub struct Sequence<T: PrimInt + Serialize> {
+    pub frames: Vec<Information<T>>,
+}
+
+/// Where the transformations between the frames come from.
+pub enum SequenceTransformations<'a> {
+    /// The transformation for every pair of the consecutive frames.
+    Given(&'a [Transformation]),
+    /// Inferred among the candidates, the pairs less similar than
+    /// min_similarity are not learnt.
+    Inferred { candidates: &'a [Transformation], min_similarity: f32 },
+}
+
+/// What happened to a frame of the sequence.
+#[derive(Serialize)]
+pub struct FrameTrace<T: PrimInt + Serialize> {
+    pub frame: usize,
+    pub name: String,
+    /// What the context space recognized in the frame before learning it.
+    pub recognized: Option<Interpretation<T>>,
+    /// Transformation from the previous frame, None for the first frame
+    /// and the frames which were not learnt.
+    pub tran: Option<Transformation>,
+    /// Similarity of the inferred transformation.
+    pub similarity: Option<f32>,
+}
+
+impl<T: PrimInt + Serialize + Sync + Send> Sequence<T> {
+    /// Reads the frames from an animated gif or from the images of a folder
+    /// in the order of their names. Other files of the folder are skipped.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Sequence<T>> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            Sequence::from_dir(path)
+        } else {
+            Sequence::from_gif(path)
+        }
+    }
+
+    /// The numbers in the names go by their value, so 2.png goes before 10.png.
+    /// All frames have to be of the size of the first one.
+    pub fn from_dir(dir: &Path) -> Result<Sequence<T>> {
+        let mut paths = image_paths(dir)?;
+        paths.sort_by(|a, b| natural_cmp(&file_name(a), &file_name(b)));
+
+        let mut frames = Vec::with_capacity(paths.len());
+        for path in paths {
+            let name = file_name(&path);
+            frames.push(Information::from_image(&image::open(&path)?, name)?);
+        }
+        Sequence::new(frames)
+    }
+
+    /// The frames are named by the file name and the frame number.
+    pub fn from_gif(path: &Path) -> Result<Sequence<T>> {
+        let decoder = GifDecoder::new(File::open(path)?)?;
+        let name = file_name(path);
+
+        let mut frames = Vec::new();
+        for (n, frame) in decoder.into_frames().collect_frames()?.into_iter().enumerate() {
+            let img = DynamicImage::ImageRgba8(frame.into_buffer());
+            frames.push(Information::from_image(&img, format!("{}#{}", name, n))?);
+        }
+        Sequence::new(frames)
+    }
+
+    /// Returns SizeMismatch if a frame is not of the size of the first one.
+    pub fn new(frames: Vec<Information<T>>) -> Result<Sequence<T>> {
+        if let Some(first) = frames.first() {
+            for frame in &frames {
+                first.check_same_size(frame)?;
+            }
+        }
+        Ok(Sequence { frames })
+    }
+
+    /// The sidecar file with the transformations of the sequence:
+    /// transformations.json, .csv or .bin in the folder of frames or
+    /// the file with the same name as the gif and the extension .json or .csv.
+    pub fn find_sidecar<P: AsRef<Path>>(path: P) -> Option<PathBuf> {
+        let path = path.as_ref();
+        let candidates: Vec<PathBuf> = if path.is_dir() {
+            SIDECAR_NAMES.iter().map(|n| path.join(n)).collect()
+        } else {
+            vec![path.with_extension("json"), path.with_extension("csv")]
+        };
+        candidates.into_iter().find(|p| p.is_file())
+    }
+
+    /// Reads the transformations from the sidecar file checking that
+    /// there is one for every pair of the consecutive frames.
+    pub fn load_sidecar<P: AsRef<Path>>(&self, path: P) -> Result<Vec<Transformation>> {
+        let ts = load_transformations(path)?;
+        let expected = self.frames.len().saturating_sub(1);
+        if ts.len() != expected {
+            return Err(Error::CountMismatch { expected, actual: ts.len() });
+        }
+        Ok(ts)
+    }
+
+    /// Goes through the frames: interprets every frame and then learns it
+    /// as the information with the previous frame as the interpretation.
+    /// Returns the trace of every frame.
+    pub fn learn(&self, cs: &mut ContextSpace<T>, transformations: &SequenceTransformations, accuracy: f32)
+        -> Result<Vec<FrameTrace<T>>> {
+        if let SequenceTransformations::Given(ts) = transformations {
+            let expected = self.frames.len().saturating_sub(1);
+            if ts.len() != expected {
+                return Err(Error::CountMismatch { expected, actual: ts.len() });
+            }
+        }
+
+        let mut trace = Vec::with_capacity(self.frames.len());
+        for (n, frame) in self.frames.iter().enumerate() {
+            let mut record = FrameTrace {
+                frame: n,
+                name: frame.name.clone(),
+                recognized: cs.interpret(frame, accuracy),
+                tran: None,
+                similarity: None,
+            };
+
+            if n > 0 {
+                let prev = &self.frames[n - 1];
+                match transformations {
+                    SequenceTransformations::Given(ts) => {
+                        cs.learn(&ts[n - 1], frame, prev.clone())?;
+                        record.tran = Some(ts[n - 1].clone());
+                    },
+                    SequenceTransformations::Inferred { candidates, min_similarity } => {
+                        if let Some((t, similarity)) = cs.learn_unlabeled(candidates, frame, prev.clone(), *min_similarity)? {
+                            record.tran = Some(t);
+                            record.similarity = Some(similarity);
+                        }
+                    },
+                }
+            }
+            trace.push(record);
+        }
+        Ok(trace)
+    }
+}
+
+/// Paths of the image files in the folder sorted by the path, so the runs
+/// do not depend on the order the file system lists them in. The files
+/// of other formats, like the sidecar ones, and the folders are skipped.
+pub fn image_paths<P: AsRef<Path>>(dir: P) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for dr in std::fs::read_dir(dir)? {
+        let path = dr?.path();
+        if path.is_file() && ImageFormat::from_path(&path).is_ok() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+pub(crate) fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Compares the names piece by piece, the pieces of digits by their value.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (a_part, a_rest) = split_part(a);
+        let (b_part, b_rest) = split_part(b);
+        if a_part.is_empty() || b_part.is_empty() {
+            return a_part.cmp(b_part);
+        }
+
+        let a_digits = a_part.starts_with(|c: char| c.is_ascii_digit());
+        let b_digits = b_part.starts_with(|c: char| c.is_ascii_digit());
+        let ordering = if a_digits && b_digits {
+            // without the leading zeros the longer number is the greater one
+            let (a_num, b_num) = (a_part.trim_start_matches('0'), b_part.trim_start_matches('0'));
+            a_num.len().cmp(&b_num.len()).then(a_num.cmp(b_num))
+        } else {
+            a_part.cmp(b_part)
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+        a = a_rest;
+        b = b_rest;
+    }
+}
+
+/// Splits off the leading piece of digits or of other characters.
+fn split_part(s: &str) -> (&str, &str) {
+    let digits = s.starts_with(|c: char| c.is_ascii_digit());
+    let end = s.find(|c: char| c.is_ascii_digit() != digits).unwrap_or(s.len());
+    s.split_at(end)
+}
+
+#[test]
+fn can_learn_sequence_from_gif_and_folder() {
+    use crate::transformations::save_transformations;
+
+    let dir = std::env::temp_dir().join(format!("diff_context_space_sequence_{}", std::process::id()));
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    std::fs::create_dir_all(dir.join("frames")).unwrap();
+
+    let mut frame = Information::<u16>::new(16, 8, String::new());
+    for x in 3..7 {
+        frame.set(x, 3, true);
+        frame.set(x, 4, true);
+    }
+    // to the right, back and to the right again
+    let ts = vec![Transformation::new(1, 0, 0.0), Transformation::new(-1, 0, 0.0), Transformation::new(1, 0, 0.0)];
+    let mut frames = vec![frame];
+    for t in &ts {
+        frames.push(t.apply_to(frames.last().unwrap()));
+    }
+
+    let gif_path = dir.join("moving.gif");
+    {
+        let mut gif = image::gif::GifEncoder::new(File::create(&gif_path).unwrap());
+        for (n, f) in frames.iter().enumerate() {
+            f.to_image().save(dir.join("frames").join(format!("{}.png", n))).unwrap();
+            gif.encode_frame(image::Frame::new(f.to_image().to_rgba8())).unwrap();
+        }
+    }
+    save_transformations(dir.join("frames/transformations.csv"), &ts).unwrap();
+    // the other files and the folders are skipped
+    std::fs::write(dir.join("frames/.DS_Store"), b"not an image").unwrap();
+    std::fs::create_dir(dir.join("frames/5.png")).unwrap();
+    let paths = image_paths(dir.join("frames")).unwrap();
+    assert_eq!(paths.iter().map(|p| file_name(p)).collect::<Vec<String>>(), vec!["0.png", "1.png", "2.png", "3.png"]);
+
+    // the frames and the sidecar from the folder
+    let from_dir = Sequence::<u16>::open(dir.join("frames")).unwrap();
+    assert_eq!(from_dir.frames.len(), 4);
+    assert_eq!(from_dir.frames[1].name, "1.png");
+    let sidecar = Sequence::<u16>::find_sidecar(dir.join("frames")).unwrap();
+    let given = from_dir.load_sidecar(sidecar).unwrap();
+
+    let mut cs = ContextSpace::<u16>::new();
+    let trace = from_dir.learn(&mut cs, &SequenceTransformations::Given(&given), 0.9).unwrap();
+    assert!(trace[0].recognized.is_none() && trace[0].tran.is_none());
+    assert!(trace[2].recognized.is_none());
+    assert!(trace[2].tran.as_ref() == Some(&ts[1]));
+    // the second shift to the right is recognized by the context learnt from the first one
+    let recognized = trace[3].recognized.as_ref().unwrap();
+    assert!(recognized.tran == ts[0]);
+    assert_eq!(recognized.name(), "0.png");
+    assert_eq!(cs.samples_count, 3);
+
+    // the same frames from the gif with the inferred transformations
+    let from_gif = Sequence::<u16>::open(&gif_path).unwrap();
+    assert_eq!(from_gif.frames.len(), 4);
+    assert!(from_gif.frames.iter().zip(&frames).all(|(a, b)| a.data == b.data));
+    assert_eq!(from_gif.frames[3].name, "moving.gif#3");
+
+    let candidates = [Transformation::new(-1, 0, 0.0), Transformation::new(0, 1, 0.0), Transformation::new(1, 0, 0.0)];
+    let mut cs = ContextSpace::<u16>::new();
+    let inferred = SequenceTransformations::Inferred { candidates: &candidates, min_similarity: 0.9 };
+    let trace = from_gif.learn(&mut cs, &inferred, 0.9).unwrap();
+    for (record, t) in trace[1..].iter().zip(&ts) {
+        assert!(record.tran.as_ref() == Some(t));
+        assert_eq!(record.similarity, Some(1.0));
+    }
+
+    let e = from_gif.learn(&mut cs, &SequenceTransformations::Given(&ts[1..]), 0.9);
+    assert!(matches!(e, Err(Error::CountMismatch { expected: 3, actual: 2 })));
+
+    // a frame of another size
+    Information::<u16>::new(12, 8, String::new()).to_image().save(dir.join("frames/4.png")).unwrap();
+    let e = Sequence::<u16>::open(dir.join("frames"));
+    assert!(matches!(e, Err(Error::SizeMismatch { expected: (16, 8), actual: (12, 8) })));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn can_sort_frames_by_numbers() {
+    let mut names = vec!["10.png", "2.png", "frame_10.png", "1.png", "frame_9.png", "010a.png", "frame.png"];
+    names.sort_by(|a, b| natural_cmp(a, b));
+    assert_eq!(names, vec!["1.png", "2.png", "10.png", "010a.png", "frame.png", "frame_9.png", "frame_10.png"]);
+}
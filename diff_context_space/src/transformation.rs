@@ -1,7 +1,14 @@
 use crate::Information;
+use crate::distance::DistanceMetric;
+use crate::information::THRESHOLD;
 
 use std::fmt;
-use std::f32::consts::{PI, FRAC_PI_6, FRAC_PI_3, FRAC_PI_2};
+use std::hash::{Hash, Hasher};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::f32::consts::PI;
+#[cfg(test)]
+use std::f32::consts::{FRAC_PI_6, FRAC_PI_3, FRAC_PI_2};
 
 extern crate num_traits;
 use num_traits::int::PrimInt;
@@ -10,71 +17,311 @@ extern crate serde;
 use serde::{Serialize, Deserialize};
 
 extern crate image;
-use image::{DynamicImage, Luma};
+use image::Luma;
 
 extern crate imageproc;
-use imageproc::geometric_transformations::{rotate_about_center, translate, Interpolation};
+use imageproc::geometric_transformations::rotate_about_center;
+#[cfg(test)]
+use imageproc::geometric_transformations::translate;
 
 /// This structure is to store the transformation
 /// applied by the context.
 /// x is horizontal, left shift is negative, right is positive.
 /// y is vertical, up is positive, down is negative.
 /// a is an angle to rotate counter clockwise about the center in radians.
+/// scale zooms the information about the center, 1 keeps the size.
+/// flip_h mirrors the left and the right sides, flip_v the top and the bottom.
+/// The scale and the flips may be omitted in the text formats.
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Transformation {
     pub x: i16,
     pub y: i16,
     pub a: f32,
+    #[serde(default = "no_scale")]
+    pub scale: f32,
+    #[serde(default)]
+    pub flip_h: bool,
+    #[serde(default)]
+    pub flip_v: bool,
+}
+
+fn no_scale() -> f32 {
+    1.0
+}
+
+/// How the pixels of the rotated information are taken from the source.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Interpolation {
+    /// The nearest pixel, the result is calculated on the bits.
+    #[default]
+    Nearest,
+    /// The weighted 4 nearest pixels.
+    Bilinear,
+    /// The weighted 16 nearest pixels.
+    Bicubic,
+}
+
+impl Interpolation {
+    fn to_imageproc(self) -> imageproc::geometric_transformations::Interpolation {
+        use imageproc::geometric_transformations::Interpolation as I;
+        match self {
+            Interpolation::Nearest => I::Nearest,
+            Interpolation::Bilinear => I::Bilinear,
+            Interpolation::Bicubic => I::Bicubic,
+        }
+    }
+}
+
+/// Settings of the rotation: the interpolation and the threshold.
+/// The bilinear and the bicubic interpolations give grey pixels,
+/// the pixels brighter than the threshold are set, the others are not.
+/// The nearest interpolation gives no grey pixels, so the threshold
+/// does not matter for it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Resampling {
+    pub interpolation: Interpolation,
+    pub threshold: u8,
+}
+
+impl Default for Resampling {
+    fn default() -> Self {
+        Resampling { interpolation: Interpolation::Nearest, threshold: THRESHOLD }
+    }
+}
+
+impl Default for Transformation {
+    fn default() -> Self {
+        Transformation::new(0, 0, 0.0)
+    }
 }
 
 impl Transformation {
+    /// The shift with the rotation, without scaling and flips.
+    pub fn new(x: i16, y: i16, a: f32) -> Transformation {
+        Transformation { x, y, a, scale: 1.0, flip_h: false, flip_v: false }
+    }
+
+    /// Tells if the transformation scales or flips the information.
+    pub fn has_scale_or_flip(&self) -> bool {
+        self.scale != 1.0 || self.flip_h || self.flip_v
+    }
+
     /// Calculates distance value to another transformation. 
     /// This method allows to measure how close is one transformation 
-    /// to another.
-    /// TODO: add logic to have seamless distance calculation for the
-    /// transformations located on the edges. The transformation on the left
-    /// top corner should be considered with small distance to the transformation
-    /// located on the bottom left corner and bottom right corner and top right 
-    /// corner.
-    /// TODO: upgrade the logic to take rotation a into account
+    /// to another. The default metric is used, see DistanceMetric.
     pub fn distance_to(&self, to: &Transformation) -> f32 {
-        let mut dh = to.x - self.x;
-        dh *= dh;
-
-        let mut dv = to.y - self.y;
-        dv *= dv;
-
-        let mut da = to.a - self.a;
-        da *= da;
-
-        let mut d = (dh + dv) as f32;
-        d += da;
+        self.distance_with(to, &DistanceMetric::default())
+    }
 
-        let r = d.sqrt();
-        r
+    /// Calculates distance value to another transformation with the given metric.
+    pub fn distance_with(&self, to: &Transformation, metric: &DistanceMetric) -> f32 {
+        metric.distance(self, to)
     }
 
+    /// Flips and scales the information about the center, moves it by the shift
+    /// and then rotates it about the center. The shift and the rotation give
+    /// the same result as imageproc translate and rotate_about_center with
+    /// the nearest interpolation, but it is calculated on the bits,
+    /// the rotations, scales and flips are looked up in the cached tables.
     pub fn apply_to<T: PrimInt + Serialize>(&self, to: &Information<T>) -> Information<T> {
-        let img = to.to_image().to_luma8();
+        self.apply_with(to, &Resampling::default())
+    }
+
+    /// The same as apply_to, but the rotation is made with the given
+    /// interpolation. The scales and the flips are always nearest.
+    pub fn apply_with<T: PrimInt + Serialize>(&self, to: &Information<T>, resampling: &Resampling) -> Information<T> {
+        let mut int = if self.has_scale_or_flip() {
+            remap(to, &scale_flip_table(to, self.scale, self.flip_h, self.flip_v))
+        } else {
+            to.clone()
+        };
 
-        // since vertical for translate function goes from up to down
+        // since vertical for the image goes from up to down
         // (it is common for computer graphics),
         // but has opposite direction for xy coordinates of a paper sheet
         // vertical is multiplied on -1.
-        let t = (self.x as i32, (self.y * -1) as i32);
+        if self.x != 0 || self.y != 0 {
+            int = int.shifted(self.x as i32, -(self.y as i32));
+        }
+
+        if self.a == 0.0 {
+            return int;
+        }
+
+        let theta = 2.0 * PI - self.a;  // positive rotation for imageproc is clockwise,
+                                        // but on a paper sheet is counter clockwise
+        if resampling.interpolation == Interpolation::Nearest {
+            return remap(&int, &rotation_table(&int, theta));
+        }
+
+        let img = int.to_image().to_luma8();
+        let img = rotate_about_center(&img, theta, resampling.interpolation.to_imageproc(), Luma([0]));
+        Information::from_luma_threshold(&img, resampling.threshold, int.name)
+    }
+
+    /// The original implementation of apply_to working on the images.
+    #[cfg(test)]
+    fn apply_by_image<T: PrimInt + Serialize>(&self, to: &Information<T>) -> Information<T> {
+        let img = to.to_image().to_luma8();
+
+        let t = (self.x as i32, -self.y as i32);
         let mut img = translate(&img, t);
 
         if self.a != 0.0 {
             let default = Luma([0]);        // black
-            let theta = 2.0 * PI - self.a;  // positive rotation for the function is clockwise,
-                                            // but on a paper sheet is counter clockwise
-            img = rotate_about_center(&img, theta, Interpolation::Nearest, default);
+            let theta = 2.0 * PI - self.a;
+            img = rotate_about_center(&img, theta, Interpolation::Nearest.to_imageproc(), default);
         }
         
-        let out_img = DynamicImage::ImageLuma8(img);
         let name = to.name.clone();
-        Information::from_image(&out_img, name)
+        Information::from_luma_threshold(&img, THRESHOLD, name)
+    }
+}
+
+/// The width, the height, the bit width of T, the parameter bits and the kind of the table.
+type TableKey = (u32, u32, u32, u32, u8);
+/// Pairs of the bit offsets (result, source), the pixels which
+/// come from outside of the information are not listed.
+type Table = Arc<Vec<(u32, u32)>>;
+
+const ROTATION: u8 = 0;
+const SCALE: u8 = 1;
+const FLIP_H: u8 = 2;
+const FLIP_V: u8 = 4;
+
+/// The cache keeps the tables with up to this many pairs in total,
+/// about 32 MB, the oldest tables are dropped first.
+const MAX_CACHED_PAIRS: usize = 1 << 22;
+
+struct TableCache {
+    tables: HashMap<TableKey, Table>,
+    order: VecDeque<TableKey>,
+    pairs: usize,
+    max_pairs: usize,
+}
+
+impl TableCache {
+    fn new(max_pairs: usize) -> TableCache {
+        TableCache { tables: HashMap::new(), order: VecDeque::new(), pairs: 0, max_pairs }
+    }
+
+    fn insert(&mut self, key: TableKey, table: Table) -> Table {
+        // the table may be built by another thread meanwhile
+        if let Some(cached) = self.tables.get(&key) {
+            return cached.clone();
+        }
+
+        self.pairs += table.len();
+        self.tables.insert(key, table.clone());
+        self.order.push_back(key);
+        while self.pairs > self.max_pairs && self.order.len() > 1 {
+            if let Some(oldest) = self.order.pop_front() {
+                if let Some(dropped) = self.tables.remove(&oldest) {
+                    self.pairs -= dropped.len();
+                }
+            }
+        }
+        table
+    }
+}
+
+/// Returns the table from the cache or builds it.
+fn cached_table<F: FnOnce() -> Vec<(u32, u32)>>(key: TableKey, build: F) -> Table {
+    static TABLES: OnceLock<Mutex<TableCache>> = OnceLock::new();
+
+    let tables = TABLES.get_or_init(|| Mutex::new(TableCache::new(MAX_CACHED_PAIRS)));
+    if let Some(table) = tables.lock().unwrap().tables.get(&key) {
+        return table.clone();
+    }
+
+    let table = Arc::new(build());
+    tables.lock().unwrap().insert(key, table)
+}
+
+/// Builds the information of the same size with the bits taken by the table.
+fn remap<T: PrimInt + Serialize>(int: &Information<T>, table: &Table) -> Information<T> {
+    let mut out = Information::new(int.width, int.height, int.name.clone());
+    for (dst, src) in table.iter() {
+        if int.get_offset(*src) {
+            out.set_offset(*dst);
+        }
     }
+    out
+}
+
+/// The table of the flips and the scaling about the center with the nearest pixels.
+fn scale_flip_table<T: PrimInt + Serialize>(int: &Information<T>, scale: f32, flip_h: bool, flip_v: bool) -> Table {
+    let kind = SCALE | if flip_h { FLIP_H } else { 0 } | if flip_v { FLIP_V } else { 0 };
+    let key = (int.width, int.height, Information::<T>::word_bits(), scale.to_bits(), kind);
+
+    cached_table(key, || {
+        let (w, h) = (int.width as f32, int.height as f32);
+        let (cx, cy) = (w / 2.0, h / 2.0);
+
+        let mut table = Vec::new();
+        for y in 0..int.height {
+            for x in 0..int.width {
+                let px = ((x as f32 - cx) / scale + cx).round();
+                let py = ((y as f32 - cy) / scale + cy).round();
+                if px < 0.0 || px >= w || py < 0.0 || py >= h {
+                    continue;
+                }
+                let px = if flip_h { int.width - 1 - px as u32 } else { px as u32 };
+                let py = if flip_v { int.height - 1 - py as u32 } else { py as u32 };
+                table.push((int.offset_of(x, y), int.offset_of(px, py)));
+            }
+        }
+        table
+    })
+}
+
+/// The table of the rotation of the information by theta clockwise.
+fn rotation_table<T: PrimInt + Serialize>(int: &Information<T>, theta: f32) -> Table {
+    let key = (int.width, int.height, Information::<T>::word_bits(), theta.to_bits(), ROTATION);
+    cached_table(key, || build_rotation_table(int, theta))
+}
+
+fn build_rotation_table<T: PrimInt + Serialize>(int: &Information<T>, theta: f32) -> Vec<(u32, u32)> {
+    // the same calculation as imageproc rotate_about_center does:
+    // the inverse of translate(cx, cy) * rotate(theta) * translate(-cx, -cy)
+    let (w, h) = (int.width as f32, int.height as f32);
+    let (cx, cy) = (w / 2.0, h / 2.0);
+    let (sin, cos) = theta.sin_cos();
+    let rotate_inverse = [cos, sin, 0.0, -sin, cos, 0.0, 0.0, 0.0, 1.0];
+    let translate_inverse = [1.0, 0.0, -cx, 0.0, 1.0, -cy, 0.0, 0.0, 1.0];
+    let translate_back_inverse = [1.0, 0.0, cx, 0.0, 1.0, cy, 0.0, 0.0, 1.0];
+    let m = mul3x3(translate_back_inverse, mul3x3(rotate_inverse, translate_inverse));
+
+    let mut table = Vec::new();
+    for y in 0..int.height {
+        for x in 0..int.width {
+            let (fx, fy) = (x as f32, y as f32);
+            let px = (m[0] * fx + m[1] * fy + m[2]).round();
+            let py = (m[3] * fx + m[4] * fy + m[5]).round();
+            if px < 0.0 || px >= w || py < 0.0 || py >= h {
+                continue;
+            }
+            table.push((int.offset_of(x, y), int.offset_of(px as u32, py as u32)));
+        }
+    }
+    table
+}
+
+/// 3x3 matrix multiplication in the same order of the operations as imageproc has.
+fn mul3x3(a: [f32; 9], b: [f32; 9]) -> [f32; 9] {
+    let [a00, a01, a02, a10, a11, a12, a20, a21, a22] = a;
+    let [b00, b01, b02, b10, b11, b12, b20, b21, b22] = b;
+    [
+        a00 * b00 + a01 * b10 + a02 * b20,
+        a00 * b01 + a01 * b11 + a02 * b21,
+        a00 * b02 + a01 * b12 + a02 * b22,
+        a10 * b00 + a11 * b10 + a12 * b20,
+        a10 * b01 + a11 * b11 + a12 * b21,
+        a10 * b02 + a11 * b12 + a12 * b22,
+        a20 * b00 + a21 * b10 + a22 * b20,
+        a20 * b01 + a21 * b11 + a22 * b21,
+        a20 * b02 + a21 * b12 + a22 * b22,
+    ]
 }
 
 impl fmt::Display for Transformation {
@@ -82,46 +329,111 @@ impl fmt::Display for Transformation {
         write!(f, "x: {} y: {} a: {}", 
                 self.x,
                 self.y,
-                self.a)
+                self.a)?;
+        // the plain shifts with rotations are printed as before
+        if self.scale != 1.0 {
+            write!(f, " scale: {}", self.scale)?;
+        }
+        if self.flip_h {
+            write!(f, " flip_h")?;
+        }
+        if self.flip_v {
+            write!(f, " flip_v")?;
+        }
+        Ok(())
+    }
+}
+
+/// Transformations are equal if they have the same shifts and flips and their
+/// angles fall into the same bin of this size, the angles are taken modulo 2π.
+pub const ANGLE_RESOLUTION: f32 = 1e-3;
+
+/// The scales are compared by the bins of this size as well.
+pub const SCALE_RESOLUTION: f32 = 1e-3;
+
+impl Transformation {
+    /// Index of the ANGLE_RESOLUTION wide bin the rotation falls into.
+    pub fn angle_bin(&self) -> u32 {
+        let bins = (2.0 * PI / ANGLE_RESOLUTION).round() as u32;
+        let bin = (self.a.rem_euclid(2.0 * PI) / ANGLE_RESOLUTION).round() as u32;
+        bin % bins
+    }
+
+    /// Index of the SCALE_RESOLUTION wide bin the scale falls into.
+    pub fn scale_bin(&self) -> i64 {
+        (self.scale / SCALE_RESOLUTION).round() as i64
     }
 }
 
 impl std::cmp::PartialEq for Transformation {
     fn eq(&self, other: &Self) -> bool {
-        self.y == other.y && self.x == other.x
+        self.y == other.y && self.x == other.x && self.angle_bin() == other.angle_bin()
+            && self.scale_bin() == other.scale_bin()
+            && self.flip_h == other.flip_h && self.flip_v == other.flip_v
+    }
+}
+
+impl std::cmp::Eq for Transformation {}
+
+impl Hash for Transformation {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.x.hash(state);
+        self.y.hash(state);
+        self.angle_bin().hash(state);
+        self.scale_bin().hash(state);
+        self.flip_h.hash(state);
+        self.flip_v.hash(state);
     }
 }
 
 #[test]
 fn distance_test() {
-    let t1 = Transformation { x: 1, y: 1, a: 0.0 };
-    let t2 = Transformation { x: 1, y: 3, a: 0.0 };
+    let t1 = Transformation::new(1, 1, 0.0);
+    let t2 = Transformation::new(1, 3, 0.0);
 
     let d = t1.distance_to(&t2);
     assert_eq!(d, 2.0);
 
-    let t2 = Transformation { x: 3, y: 1, a: 0.0 };
+    let t2 = Transformation::new(3, 1, 0.0);
     let d = t1.distance_to(&t2);
     assert_eq!(d, 2.0);
 
-    let t2 = Transformation { x: 2, y: 2, a: 0.0 };
+    let t2 = Transformation::new(2, 2, 0.0);
     let d = t1.distance_to(&t2);
-    assert_eq!(d, 1.4142135);
+    assert_eq!(d, std::f32::consts::SQRT_2);
 
-    let t2 = Transformation { x: 3, y: 3, a: 0.0 };
+    let t2 = Transformation::new(3, 3, 0.0);
     let d = t1.distance_to(&t2);
     assert_eq!(d, 2.828427);
 }
 
+#[test]
+fn can_compare_angles() {
+    let t = Transformation::new(1, -2, FRAC_PI_2);
+
+    assert!(t == Transformation::new(1, -2, FRAC_PI_2 + 1e-5));
+    assert!(t == Transformation::new(1, -2, FRAC_PI_2 - 2.0 * PI));
+    assert!(t != Transformation::new(1, -2, FRAC_PI_3));
+    assert!(t != Transformation::new(1, -1, FRAC_PI_2));
+    assert!(Transformation::new(0, 0, 0.0) == Transformation::new(0, 0, 2.0 * PI));
+
+    let set: std::collections::HashSet<Transformation> = vec![
+        t.clone(),
+        Transformation::new(1, -2, FRAC_PI_2 + 2.0 * PI),
+        Transformation::new(1, -2, 0.0),
+    ].into_iter().collect();
+    assert_eq!(set.len(), 2);
+}
+
 #[test]
 fn can_shift_h_right() {
-    let t = Transformation { x: 1, y: 0, a: 0.0 };
+    let t = Transformation::new(1, 0, 0.0);
 
-    let i = Information { data: Vec::<i8>::from([
+    let i = Information::from_rows(Vec::<i8>::from([
         0b_0010_0000,
         0b_0010_0000,
         0b_0010_0000
-    ])};
+    ]), String::new());
 
     let int = t.apply_to(&i);
 
@@ -132,14 +444,101 @@ fn can_shift_h_right() {
     ]));
 }
 
+#[test]
+fn can_rotate_with_interpolation() {
+    let mut int = Information::<u32>::new(24, 24, String::new());
+    for x in 2..22 {
+        int.set(x, 12, true);
+    }
+    let t = Transformation::new(0, 0, FRAC_PI_6);
+
+    // count the pixels reachable from the first one by the 8 neighbours
+    let connected = |i: &Information<u32>| {
+        let mut seen = Information::<u32>::new(i.width, i.height, String::new());
+        let mut stack: Vec<(u32, u32)> = (0..i.height).flat_map(|y| (0..i.width).map(move |x| (x, y)))
+            .filter(|(x, y)| i.get(*x, *y)).take(1).collect();
+        let mut count = 0;
+        while let Some((x, y)) = stack.pop() {
+            if seen.get(x, y) {
+                continue;
+            }
+            seen.set(x, y, true);
+            count += 1;
+            for (nx, ny) in [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1),
+                (x.wrapping_sub(1), y.wrapping_sub(1)), (x + 1, y + 1), (x.wrapping_sub(1), y + 1), (x + 1, y.wrapping_sub(1))] {
+                if nx < i.width && ny < i.height && i.get(nx, ny) {
+                    stack.push((nx, ny));
+                }
+            }
+        }
+        count
+    };
+
+    let nearest = t.apply_to(&int);
+    let bilinear = t.apply_with(&int, &Resampling { interpolation: Interpolation::Bilinear, threshold: 50 });
+    let bicubic = t.apply_with(&int, &Resampling { interpolation: Interpolation::Bicubic, threshold: 50 });
+    assert!(nearest == t.apply_with(&int, &Resampling { threshold: 200, ..Resampling::default() }));
+
+    for i in [&bilinear, &bicubic] {
+        assert_eq!(connected(i), i.ones().count());
+        assert!(i.ones().count() >= nearest.ones().count());
+    }
+
+    // nothing is brighter than the white
+    let none = t.apply_with(&int, &Resampling { interpolation: Interpolation::Bilinear, threshold: 255 });
+    assert_eq!(none.ones().count(), 0);
+}
+
+#[test]
+fn can_flip() {
+    let i = Information::from_rows(vec![
+        0b_0110_0000u8,
+        0b_0010_0000,
+        0b_0000_0000
+    ], String::new());
+
+    let t = Transformation { flip_h: true, ..Transformation::new(0, 0, 0.0) };
+    assert_eq!(t.apply_to(&i).data, vec![
+        0b_0000_0110,
+        0b_0000_0100,
+        0b_0000_0000
+    ]);
+
+    let t = Transformation { flip_v: true, ..Transformation::new(1, 0, 0.0) };
+    assert_eq!(t.apply_to(&i).data, vec![
+        0b_0000_0000,
+        0b_0001_0000,
+        0b_0011_0000
+    ]);
+}
+
+#[test]
+fn can_scale_about_center() {
+    let i = Information::from_rows(vec![
+        0b_0000_0000u8,
+        0b_0001_0000,
+        0b_0000_0000,
+        0b_0000_0000
+    ], String::new());
+
+    // the pixel at (3, 1) is twice as far from the center (4, 2)
+    let t = Transformation { scale: 2.0, ..Transformation::new(0, 0, 0.0) };
+    assert_eq!(t.apply_to(&i).data, vec![
+        0b_0110_0000,
+        0b_0000_0000,
+        0b_0000_0000,
+        0b_0000_0000
+    ]);
+}
+
 #[test]
 fn can_shift_h_left() {
-    let t = Transformation { x: -1, y: 0, a: 0.0 };
-    let i = Information { data: vec![
+    let t = Transformation::new(-1, 0, 0.0);
+    let i = Information::from_rows(vec![
         0b_0010_0000,
         0b_0010_0000,
         0b_0010_0000
-    ]};
+    ], String::new());
 
     let int = t.apply_to(&i);
 
@@ -152,12 +551,12 @@ fn can_shift_h_left() {
 
 #[test]
 fn can_shift_vertical_up() {
-    let t = Transformation { x: 0, y: 1, a: 0.0 };
-    let i = Information { data: Vec::<i8>::from([
+    let t = Transformation::new(0, 1, 0.0);
+    let i = Information::from_rows(Vec::<i8>::from([
         0b_0000_0000,
         0b_0010_0000,
         0b_0000_0000
-    ])};
+    ]), String::new());
 
     let int = t.apply_to(&i);
 
@@ -170,12 +569,12 @@ fn can_shift_vertical_up() {
 
 #[test]
 fn can_shift_vertical_down() {
-    let t = Transformation { x: 0, y: -1, a: 0.0 };
-    let i = Information { data: vec![
+    let t = Transformation::new(0, -1, 0.0);
+    let i = Information::from_rows(vec![
         0b_0000_0000,
         0b_0010_0000,
         0b_0000_0000
-    ]};
+    ], String::new());
 
     let int = t.apply_to(&i);
 
@@ -188,12 +587,12 @@ fn can_shift_vertical_down() {
 
 #[test]
 fn can_shift_vertical_up_and_h_right() {
-    let t = Transformation { x: 1, y: 1, a: 0.0 };
-    let i = Information { data: vec![
+    let t = Transformation::new(1, 1, 0.0);
+    let i = Information::from_rows(vec![
         0b_0000_0000,
         0b_0010_0000,
         0b_0000_0000
-    ]};
+    ], String::new());
 
     let int = t.apply_to(&i);
 
@@ -206,12 +605,12 @@ fn can_shift_vertical_up_and_h_right() {
 
 #[test]
 fn can_shift_vertical_down_and_h_left() {
-    let t = Transformation { x: -1, y: -1, a: 0.0 };
-    let i = Information { data: Vec::<i8>::from([
+    let t = Transformation::new(-1, -1, 0.0);
+    let i = Information::from_rows(Vec::<i8>::from([
         0b_0000_0000,
         0b_0010_0000,
         0b_0000_0000
-    ])};
+    ]), String::new());
 
     let int = t.apply_to(&i);
 
@@ -222,11 +621,30 @@ fn can_shift_vertical_down_and_h_left() {
     ]));
 }
 
+#[test]
+fn can_shift_h_right_across_elements() {
+    let t = Transformation::new(1, 0, 0.0);
+
+    // 12x2 information kept in two elements per row
+    let mut i = Information::<u8>::new(12, 2, String::new());
+    i.set(3, 0, true);
+    i.set(11, 1, true);
+
+    let int = t.apply_to(&i);
+
+    assert_eq!(int.width, 12);
+    assert_eq!(int.height, 2);
+    assert_eq!(int.data, vec![
+        0b_1000_0000, 0b_0000_0000, // 0, moved from the higher element to the lower one
+        0b_0000_0000, 0b_0000_0000, // 1, moved out of the right edge
+    ]);
+}
+
 #[test]
 fn can_rotate_frac_pi_6() {
     let a = FRAC_PI_6;
-    let t = Transformation { x: 0, y: 0, a: a };
-    let i = Information { data: Vec::<u16>::from([
+    let t = Transformation::new(0, 0, a);
+    let i = Information::from_rows(Vec::<u16>::from([
         0b_0000000000000000, // 0
         0b_0000000000000000, // 1
         0b_0000000000000000, // 2
@@ -235,7 +653,7 @@ fn can_rotate_frac_pi_6() {
         0b_0000000000000000, // 5
         0b_0000000000000000, // 6
         0b_0000000000000000, // 7
-    ])};
+    ]), String::new());
 
     let int = t.apply_to(&i);
 
@@ -254,8 +672,8 @@ fn can_rotate_frac_pi_6() {
 #[test]
 fn can_rotate_frac_pi_3() {
     let a = FRAC_PI_3;
-    let t = Transformation { x: 0, y: 0, a: a };
-    let i = Information { data: Vec::<u16>::from([
+    let t = Transformation::new(0, 0, a);
+    let i = Information::from_rows(Vec::<u16>::from([
         0b_0000000000000000, // 0
         0b_0000000000000000, // 1
         0b_0000000000000000, // 2
@@ -264,7 +682,7 @@ fn can_rotate_frac_pi_3() {
         0b_0000000000000000, // 5
         0b_0000000000000000, // 6
         0b_0000000000000000, // 7
-    ])};
+    ]), String::new());
 
     let int = t.apply_to(&i);
 
@@ -283,8 +701,8 @@ fn can_rotate_frac_pi_3() {
 #[test]
 fn can_rotate_frac_pi_2() {
     let a = FRAC_PI_2;
-    let t = Transformation { x: 0, y: 0, a: a };
-    let i = Information { data: Vec::<u16>::from([
+    let t = Transformation::new(0, 0, a);
+    let i = Information::from_rows(Vec::<u16>::from([
         0b_0000000000000000, // 0
         0b_0000000000000000, // 1
         0b_0000000000000000, // 2
@@ -293,7 +711,7 @@ fn can_rotate_frac_pi_2() {
         0b_0000000000000000, // 5
         0b_0000000000000000, // 6
         0b_0000000000000000, // 7
-    ])};
+    ]), String::new());
 
     let int = t.apply_to(&i);
 
@@ -312,8 +730,8 @@ fn can_rotate_frac_pi_2() {
 #[test]
 fn can_rotate_frac_2pi_3() {
     let a = 2.0 * FRAC_PI_3;
-    let t = Transformation { x: 0, y: 0, a: a };
-    let i = Information { data: Vec::<u16>::from([
+    let t = Transformation::new(0, 0, a);
+    let i = Information::from_rows(Vec::<u16>::from([
         0b_0000000000000000, // 0
         0b_0000000000000000, // 1
         0b_0000000000000000, // 2
@@ -322,7 +740,7 @@ fn can_rotate_frac_2pi_3() {
         0b_0000000000000000, // 5
         0b_0000000000000000, // 6
         0b_0000000000000000, // 7
-    ])};
+    ]), String::new());
 
     let int = t.apply_to(&i);
 
@@ -341,8 +759,8 @@ fn can_rotate_frac_2pi_3() {
 #[test]
 fn can_rotate_frac_5pi_6() {
     let a = 5.0 * FRAC_PI_6;
-    let t = Transformation { x: 0, y: 0, a: a };
-    let i = Information { data: Vec::<u16>::from([
+    let t = Transformation::new(0, 0, a);
+    let i = Information::from_rows(Vec::<u16>::from([
         0b_0000000000000000, // 0
         0b_0000000000000000, // 1
         0b_0000000000000000, // 2
@@ -351,7 +769,7 @@ fn can_rotate_frac_5pi_6() {
         0b_0000000000000000, // 5
         0b_0000000000000000, // 6
         0b_0000000000000000, // 7
-    ])};
+    ]), String::new());
 
     let int = t.apply_to(&i);
 
@@ -370,8 +788,8 @@ fn can_rotate_frac_5pi_6() {
 #[test]
 fn can_rotate_2pi() {
     let a = 2.0 * PI;
-    let t = Transformation { x: 0, y: 0, a: a };
-    let i = Information { data: Vec::<u16>::from([
+    let t = Transformation::new(0, 0, a);
+    let i = Information::from_rows(Vec::<u16>::from([
         0b_0000000000000000, // 0
         0b_0000000000000000, // 1
         0b_0000000000000000, // 2
@@ -380,7 +798,7 @@ fn can_rotate_2pi() {
         0b_0000000000000000, // 5
         0b_0000000000000000, // 6
         0b_0000000000000000, // 7
-    ])};
+    ]), String::new());
 
     let int = t.apply_to(&i);
 
@@ -394,4 +812,58 @@ fn can_rotate_2pi() {
         0b_0000000000000000, // 6
         0b_0000000000000000, // 7
     ]);
-}
\ No newline at end of file
+}
+
+#[test]
+fn can_apply_the_same_as_images() {
+    fn check<T: PrimInt + Serialize + fmt::Debug>(width: u32, height: u32) {
+        // a pattern touching the edges
+        let mut int = Information::<T>::new(width, height, String::from("pattern"));
+        for y in 0..height {
+            for x in 0..width {
+                if (x * 7 + y * 3) % 5 == 0 || x == 0 || y == height - 1 {
+                    int.set(x, y, true);
+                }
+            }
+        }
+
+        let angles = [0.0, FRAC_PI_6, PI / 4.0, FRAC_PI_2, 3.0 * PI / 4.0, 2.0, 5.5];
+        for x in [-40i16, -9, -3, -1, 0, 1, 2, 8, 40].iter() {
+            for y in [-40i16, -5, -1, 0, 1, 3, 40].iter() {
+                for a in angles.iter() {
+                    let t = Transformation::new(*x, *y, *a);
+                    let expected = t.apply_by_image(&int);
+                    let actual = t.apply_to(&int);
+                    assert!(actual == expected, "{}x{} {}", width, height, t);
+                }
+            }
+        }
+    }
+
+    check::<u8>(12, 7);
+    check::<i8>(8, 8);
+    check::<u16>(16, 16);
+    check::<u32>(37, 20);
+    check::<u64>(64, 9);
+}
+
+#[test]
+fn can_drop_the_oldest_tables() {
+    let mut cache = TableCache::new(10);
+    let key = |n: u32| (8, 8, 8, n, ROTATION);
+    cache.insert(key(0), Arc::new(vec![(0, 0); 4]));
+    cache.insert(key(1), Arc::new(vec![(0, 0); 4]));
+    cache.insert(key(2), Arc::new(vec![(0, 0); 4]));
+    assert!(!cache.tables.contains_key(&key(0)));
+    assert!(cache.tables.contains_key(&key(1)) && cache.tables.contains_key(&key(2)));
+    assert_eq!(cache.pairs, 8);
+
+    // the table built twice is kept once
+    cache.insert(key(2), Arc::new(vec![(0, 0); 4]));
+    assert_eq!(cache.pairs, 8);
+
+    // the table larger than the limit is still kept until the next one
+    cache.insert(key(3), Arc::new(vec![(0, 0); 20]));
+    assert_eq!(cache.tables.len(), 1);
+    assert_eq!(cache.pairs, 20);
+}
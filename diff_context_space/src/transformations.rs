@@ -1,101 +1,408 @@
 use std::fs::File;
-use std::path::PathBuf;
-use std::f32::consts::{PI, FRAC_PI_8, FRAC_PI_4, FRAC_PI_2};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::f32::consts::{FRAC_PI_4, PI};
 
-use crate::{ Transformation };
+use crate::{ Transformation, Information };
+use crate::legacy::TransformationV0;
+use crate::error::{Error, Result};
 
-pub fn build_and_save_transformations(out_file_path: PathBuf, size: u8, use_rotation: bool) {
-    // generate transformations for every shift left/right/up/down
+extern crate serde_json;
+extern crate csv;
 
-    let t_count:usize = size as usize * size as usize;
-    let mut transformations = Vec::<Transformation>::with_capacity(t_count);
+extern crate rayon;
+use rayon::prelude::*;
 
-    let max_x_shift = ((size / 2) + 1) as i16;
-    let max_y_shift = ((size / 2) + 1) as i16;
+extern crate num_traits;
+use num_traits::int::PrimInt;
 
-    for y_shift in 0..max_y_shift {
-        for x_shift in 0..max_x_shift {
-            let n_y_shift = -1 * y_shift;
-            let n_x_shift = -1 * x_shift;
+extern crate serde;
+use serde::Serialize;
 
-            let mut ts = get_transformations(x_shift, y_shift, use_rotation);
-            transformations.append(&mut ts);
+extern crate ordered_float;
+use ordered_float::OrderedFloat;
 
-            if n_y_shift != 0 {
-                let mut ts = get_transformations(x_shift, n_y_shift, use_rotation);
-                transformations.append(&mut ts);
-            }
-            
-            if n_x_shift != 0 {
-                let mut ts = get_transformations(n_x_shift, y_shift, use_rotation);
-                transformations.append(&mut ts);
-            }            
-
-            if n_y_shift != 0 && n_x_shift != 0 {
-                let mut ts = get_transformations(n_x_shift, n_y_shift, use_rotation);
-                transformations.append(&mut ts);
+/// Builds the set of the transformations: every shift of the ranges
+/// with every angle, every scale and the flips.
+/// The default set has only the transformation doing nothing.
+#[derive(Clone, Debug)]
+pub struct TransformationSetBuilder {
+    x_range: (i16, i16),
+    y_range: (i16, i16),
+    shift_step: i16,
+    angles: Vec<f32>,
+    scales: Vec<f32>,
+    flips: bool,
+}
+
+impl Default for TransformationSetBuilder {
+    fn default() -> Self {
+        TransformationSetBuilder {
+            x_range: (0, 0),
+            y_range: (0, 0),
+            shift_step: 1,
+            angles: vec![0.0],
+            scales: vec![1.0],
+            flips: false,
+        }
+    }
+}
+
+impl TransformationSetBuilder {
+    pub fn new() -> TransformationSetBuilder {
+        TransformationSetBuilder::default()
+    }
+
+    /// Horizontal shifts from min to max inclusive.
+    pub fn x_range(mut self, min: i16, max: i16) -> Self {
+        self.x_range = (min.min(max), min.max(max));
+        self
+    }
+
+    /// Vertical shifts from min to max inclusive.
+    pub fn y_range(mut self, min: i16, max: i16) -> Self {
+        self.y_range = (min.min(max), min.max(max));
+        self
+    }
+
+    /// Step between the shifts of both axes counted from 0,
+    /// so the shift 0 is always in the set if the range has it.
+    pub fn shift_step(mut self, step: i16) -> Self {
+        self.shift_step = step.max(1);
+        self
+    }
+
+    /// Angles from 0 up to π with the step, π is not included
+    /// since the rotations up to π are enough for the most of the shapes.
+    pub fn angle_step(mut self, step: f32) -> Self {
+        self.angles = vec![0.0];
+        if step > 0.0 {
+            let count = (PI / step - 1e-3).ceil() as usize;
+            self.angles = (0..count).map(|n| n as f32 * step).collect();
+        }
+        self
+    }
+
+    /// The explicit list of the angles.
+    pub fn angles(mut self, angles: &[f32]) -> Self {
+        self.angles = angles.to_vec();
+        self
+    }
+
+    /// Scales other than 1 to add, the scale 1 is always in the set.
+    /// The scales must be positive, build returns an error otherwise.
+    pub fn scales(mut self, scales: &[f32]) -> Self {
+        self.scales = vec![1.0];
+        self.scales.extend(scales.iter().filter(|s| **s != 1.0));
+        self
+    }
+
+    /// Adds the horizontally and the vertically flipped transformations.
+    pub fn flips(mut self, flips: bool) -> Self {
+        self.flips = flips;
+        self
+    }
+
+    /// Number of the transformations the set will have.
+    pub fn count(&self) -> usize {
+        let flips = if self.flips { 3 } else { 1 };
+        self.shifts(self.x_range).len() * self.shifts(self.y_range).len()
+            * self.angles.len() * self.scales.len() * flips
+    }
+
+    /// Builds the set, returns InvalidScale if a scale is not positive.
+    pub fn build(&self) -> Result<Vec<Transformation>> {
+        if let Some(scale) = self.scales.iter().find(|s| s.is_nan() || **s <= 0.0) {
+            return Err(Error::InvalidScale(*scale));
+        }
+
+        let mut flips = vec![(false, false)];
+        if self.flips {
+            flips.push((true, false));
+            flips.push((false, true));
+        }
+
+        let mut r = Vec::with_capacity(self.count());
+        for y in self.shifts(self.y_range) {
+            for x in self.shifts(self.x_range) {
+                for a in &self.angles {
+                    for scale in &self.scales {
+                        for (flip_h, flip_v) in &flips {
+                            r.push(Transformation { scale: *scale, flip_h: *flip_h, flip_v: *flip_v,
+                                ..Transformation::new(x, y, *a) });
+                        }
+                    }
+                }
             }
         }
+        Ok(r)
+    }
+
+    /// Builds the set and writes it into the file in the format
+    /// given by its extension, see save_transformations.
+    /// Returns the number of the transformations.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<usize> {
+        let transformations = self.build()?;
+        save_transformations(path, &transformations)?;
+        Ok(transformations.len())
+    }
+
+    fn shifts(&self, (min, max): (i16, i16)) -> Vec<i16> {
+        (min..=max).filter(|s| s % self.shift_step == 0).collect()
+    }
+}
+
+/// Generates the shifts (with the rotations by π/4 if use_rotation is set)
+/// for the information of the given size, the shifts go up to a half of the size
+/// both ways. The scales and the flips are made by TransformationSetBuilder.
+pub fn build_and_save_transformations(out_file_path: PathBuf, size: u8, use_rotation: bool) -> Result<()> {
+    let max_shift = (size / 2) as i16;
+    let mut builder = TransformationSetBuilder::new()
+        .x_range(-max_shift, max_shift)
+        .y_range(-max_shift, max_shift);
+    if use_rotation {
+        builder = builder.angle_step(FRAC_PI_4);
+    }
+
+    builder.save(out_file_path)?;
+    Ok(())
+}
+
+/// Finds the candidate transformation which turns int into the information
+/// the most similar to i, see Information::similarity_to. The first of the
+/// equally good candidates is taken. Returns the transformation and the
+/// similarity, None if nothing is similar at all.
+pub fn infer_transformation<T>(candidates: &[Transformation], i: &Information<T>, int: &Information<T>)
+    -> Option<(Transformation, f32)>
+    where T: PrimInt + Serialize + Sync + Send {
+    let (idx, similarity) = candidates.par_iter()
+        .enumerate()
+        .filter_map(|(idx, t)| {
+            let similarity = t.apply_to(int).similarity_to(i).ok()?;
+            if similarity > 0.0 { Some((idx, OrderedFloat(similarity))) } else { None }
+        })
+        .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))?;
+
+    Some((candidates[idx].clone(), similarity.into_inner()))
+}
+
+/// Formats of the transformation set files.
+/// The text ones have a transformation per object or line with the fields
+/// x, y, a, scale, flip_h and flip_v, the last three may be omitted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransformationFormat {
+    Bincode,
+    Json,
+    Csv,
+}
+
+impl TransformationFormat {
+    /// The format by the file extension: .json, .csv or bincode for any other.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> TransformationFormat {
+        let ext = path.as_ref().extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+
+        match ext.as_deref() {
+            Some("json") => TransformationFormat::Json,
+            Some("csv") => TransformationFormat::Csv,
+            _ => TransformationFormat::Bincode,
+        }
     }
+}
+
+/// Reads the transformations saved by build_and_save_transformations
+/// or save_transformations, the format is given by the file extension.
+/// The bincode files saved before the transformations got the scale and
+/// the flips are read as well.
+pub fn load_transformations<P: AsRef<Path>>(path: P) -> Result<Vec<Transformation>> {
+    let format = TransformationFormat::from_path(&path);
+    read_transformations(BufReader::new(File::open(path)?), format)
+}
+
+/// Writes the transformations in the format given by the file extension.
+pub fn save_transformations<P: AsRef<Path>>(path: P, transformations: &[Transformation]) -> Result<()> {
+    let format = TransformationFormat::from_path(&path);
+    let mut w = BufWriter::new(File::create(path)?);
+    write_transformations(&mut w, transformations, format)?;
+    w.flush()?;
+    Ok(())
+}
+
+pub fn read_transformations<R: Read>(mut r: R, format: TransformationFormat) -> Result<Vec<Transformation>> {
+    match format {
+        TransformationFormat::Bincode => {
+            let mut bytes = Vec::new();
+            r.read_to_end(&mut bytes)?;
+
+            match bincode::deserialize::<Vec<Transformation>>(&bytes) {
+                Ok(transformations) => Ok(transformations),
+                Err(e) => match bincode::deserialize::<Vec<TransformationV0>>(&bytes) {
+                    Ok(old) => Ok(old.into_iter().map(|t| t.upgrade()).collect()),
+                    Err(_) => Err(e.into()),
+                }
+            }
+        },
+        TransformationFormat::Json => Ok(serde_json::from_reader(r)?),
+        TransformationFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(r);
+            let mut transformations = Vec::new();
+            for t in reader.deserialize() {
+                transformations.push(t?);
+            }
+            Ok(transformations)
+        },
+    }
+}
+
+pub fn write_transformations<W: Write>(w: W, transformations: &[Transformation], format: TransformationFormat) -> Result<()> {
+    match format {
+        TransformationFormat::Bincode => bincode::serialize_into(w, transformations)?,
+        TransformationFormat::Json => serde_json::to_writer_pretty(w, transformations)?,
+        TransformationFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(w);
+            for t in transformations {
+                writer.serialize(t)?;
+            }
+            writer.flush()?;
+        },
+    }
+    Ok(())
+}
 
-    println!("Generated {} transformations.", transformations.len());
+#[test]
+fn can_load_transformations_without_scale() {
+    let mut path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("files/transformations/t_64x64.bin");
 
-    let f = File::create(out_file_path)
-                    .expect("Unable to create the file.");
+    let ts = load_transformations(&path).unwrap();
+    assert!(!ts.is_empty());
+    assert!(ts.iter().all(|t| !t.has_scale_or_flip()));
+}
+
+#[test]
+fn can_build_and_save_shifts() {
+    let path = std::env::temp_dir().join(format!("diff_context_space_shifts_{}.bin", std::process::id()));
+    build_and_save_transformations(path.clone(), 4, true).unwrap();
+
+    // 25 shifts from -2 to 2 with 4 angles
+    let ts = load_transformations(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(ts.len(), 25 * 4);
+    assert!(ts.iter().all(|t| !t.has_scale_or_flip()));
+}
+
+#[test]
+fn can_build_scales_and_flips() {
+    let path = std::env::temp_dir().join(format!("diff_context_space_scale_flip_{}.bin", std::process::id()));
+    let count = TransformationSetBuilder::new()
+        .x_range(-2, 2)
+        .y_range(-2, 2)
+        .scales(&[0.5, 2.0])
+        .flips(true)
+        .save(&path)
+        .unwrap();
 
-    bincode::serialize_into(f, &transformations)
-        .expect("Unable to write json to the file.");
+    // 25 shifts from -2 to 2, 3 scales, no flip and 2 flips
+    let ts = load_transformations(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(count, 25 * 3 * 3);
+    assert_eq!(ts.len(), count);
+    assert!(ts.contains(&Transformation { scale: 2.0, flip_v: true, ..Transformation::new(-2, 1, 0.0) }));
 }
 
-/// Builds all rotation transformations for the given xy.
-/// The expected rotations are:
-/// 0       = 0 deg
-/// Pi/8    = 22.5 deg
-/// Pi/4    = 45 deg
-/// 3Pi/8   = 67.5 deg
-/// Pi/2    = 90 deg
-/// 5Pi/8   = 112.5 deg
-/// 3Pi/4   = 135 deg
-/// 7Pi/8   = 157.5 deg
-/// Pi is removed since it is equal to 0
-fn get_transformations(x: i16, y: i16, use_rotation: bool) -> Vec<Transformation> {
-    let mut r = Vec::new();
-    
-    // 0
-    let t = Transformation {x, y, a: 0.0};
-    r.push(t);
+#[test]
+fn can_build_the_same_set_as_saved() {
+    use std::collections::HashSet;
+
+    let mut path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("files/transformations/t_64x64_0_Pi4_Pi2_3Pi4_4356.bin");
+    let saved: HashSet<Transformation> = load_transformations(&path).unwrap().into_iter().collect();
 
-    if !use_rotation {
-        return r;
+    let builder = TransformationSetBuilder::new()
+        .x_range(-16, 16)
+        .y_range(-16, 16)
+        .angle_step(FRAC_PI_4);
+    let built: HashSet<Transformation> = builder.build().unwrap().into_iter().collect();
+
+    assert_eq!(builder.count(), 4356);
+    assert!(built == saved);
+}
+
+#[test]
+fn can_build_with_steps() {
+    let ts = TransformationSetBuilder::new()
+        .x_range(-3, 4)
+        .y_range(1, 2)
+        .shift_step(2)
+        .angles(&[0.0, 1.0, 2.0])
+        .build()
+        .unwrap();
+
+    // x is -2, 0, 2, 4 and y is 2 only
+    assert_eq!(ts.len(), 4 * 3);
+    assert!(ts.iter().all(|t| t.y == 2 && t.x % 2 == 0));
+    assert_eq!(TransformationSetBuilder::new().angle_step(PI / 3.0).build().unwrap().len(), 3);
+}
+
+#[test]
+fn can_reject_scales_not_positive() {
+    for scale in &[0.0, -2.0, f32::NAN] {
+        match TransformationSetBuilder::new().scales(&[0.5, *scale]).build() {
+            Err(Error::InvalidScale(s)) => assert!(s.to_bits() == scale.to_bits()),
+            _ => panic!("the scale {} is accepted", scale),
+        }
+    }
+}
+
+#[test]
+fn can_convert_to_text_and_back() {
+    let ts = vec![
+        Transformation::new(-3, 2, 0.5),
+        Transformation { scale: 2.0, flip_h: true, ..Transformation::new(0, -1, 0.0) },
+    ];
+
+    for format in [TransformationFormat::Json, TransformationFormat::Csv, TransformationFormat::Bincode] {
+        let mut bytes = Vec::new();
+        write_transformations(&mut bytes, &ts, format).unwrap();
+        let read = read_transformations(&bytes[..], format).unwrap();
+        assert!(read == ts);
     }
 
-    // // Pi/8
-    // let t = Transformation {x, y, a: FRAC_PI_8};
-    // r.push(t);
+    let csv = "x,y,a\n1, -2, 0.25\n";
+    let read = read_transformations(csv.as_bytes(), TransformationFormat::Csv).unwrap();
+    assert!(read == vec![Transformation::new(1, -2, 0.25)]);
 
-    // Pi/4
-    let t = Transformation {x, y, a: FRAC_PI_4};
-    r.push(t);
-    
-    // // 3Pi/8
-    // let t = Transformation {x, y, a: 3.0 * FRAC_PI_8};
-    // r.push(t);
+    let json = r#"[{"x": 0, "y": 1, "a": 0.0, "flip_v": true}]"#;
+    let read = read_transformations(json.as_bytes(), TransformationFormat::Json).unwrap();
+    assert!(read == vec![Transformation { flip_v: true, ..Transformation::new(0, 1, 0.0) }]);
 
-    // Pi/2
-    let t = Transformation {x, y, a: FRAC_PI_2};
-    r.push(t);
+    assert_eq!(TransformationFormat::from_path("t_64x64.bin"), TransformationFormat::Bincode);
+    assert_eq!(TransformationFormat::from_path("curated.CSV"), TransformationFormat::Csv);
+}
 
-    // // 5Pi/8
-    // let t = Transformation {x, y, a: 5.0 * FRAC_PI_8};
-    // r.push(t);
+#[test]
+fn can_infer_transformation() {
+    let mut int = Information::<u16>::new(12, 8, String::from("corner"));
+    for x in 3..8 {
+        int.set(x, 2, true);
+    }
+    for y in 2..6 {
+        int.set(3, y, true);
+    }
 
-    // 3Pi/4
-    let t = Transformation {x, y, a: 3.0 * FRAC_PI_4};
-    r.push(t);
+    let candidates = TransformationSetBuilder::new()
+        .x_range(-2, 2)
+        .y_range(-2, 2)
+        .angles(&[0.0, FRAC_PI_4])
+        .build()
+        .unwrap();
+    let t = Transformation::new(2, -1, 0.0);
+    let (inferred, similarity) = infer_transformation(&candidates, &t.apply_to(&int), &int).unwrap();
 
-    // // 7Pi/8
-    // let t = Transformation {x, y, a: 7.0 * FRAC_PI_8};
-    // r.push(t);
+    assert!(inferred == t);
+    assert_eq!(similarity, 1.0);
 
-    r
-}
\ No newline at end of file
+    let empty = Information::<u16>::new(12, 8, String::new());
+    assert!(infer_transformation(&candidates, &empty, &int).is_none());
+}